@@ -1,16 +1,33 @@
 /// Run ASV
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
-use std::process::{Output, Stdio};
+use std::process::Stdio;
+use std::sync::Arc;
 
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use serde::Deserialize;
 use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
 
-use crate::repo_cache::sync_repo;
+use crate::dirs::{CACHE_DIR, DATA_DIR};
+use crate::event::Filter;
+use crate::history::History;
+use crate::repo_cache::{local_commit, sync_repo};
+use crate::repo_config::{Comments, Limits, RepoConfig, Timeouts};
 use crate::traits::RunConfig;
+use comparison::Comparison;
+use process::{JobLog, Phase, Process};
+use results::Results;
+
+pub(crate) mod chart;
+pub(crate) mod comparison;
+pub(crate) mod process;
+pub(crate) mod progress;
+pub(crate) mod results;
+pub(crate) mod source;
+mod statistics;
 
 #[derive(Default, Debug, Clone)]
 pub(crate) struct EnvSpecs(pub Vec<String>);
@@ -22,99 +39,132 @@ impl EnvSpecs {
             .flat_map(|env_spec| ["-E", env_spec])
             .collect()
     }
+    /// Keep only environments containing one of the `patterns`. Keeps all if `patterns` is empty.
+    pub fn matching(self, patterns: &[String]) -> Result<Self> {
+        if patterns.is_empty() {
+            return Ok(self);
+        }
+        let env_specs: Vec<String> = self
+            .0
+            .into_iter()
+            .filter(|env_spec| patterns.iter().any(|p| env_spec.contains(p.as_str())))
+            .collect();
+        if env_specs.is_empty() {
+            bail!("No environment matches any of {patterns:?}");
+        }
+        Ok(Self(env_specs))
+    }
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct RunResult {
     pub success: bool,
     pub wd: PathBuf,
+    /// Where asv stores results, see [`results`]
+    pub results_dir: PathBuf,
+    /// Where the benchmarks are defined, see [`source`]
+    pub benchmark_dir: PathBuf,
+    /// Configured thresholds for comparisons
+    pub thresholds: comparison::Thresholds,
     pub env_specs: EnvSpecs,
+    /// Configured timeouts for `asv` processes
+    pub timeouts: Timeouts,
+    /// Configured resource limits for `asv` processes
+    pub limits: Limits,
+    /// Configured way of commenting on PRs
+    pub comments: Comments,
 }
 
-/// Sync repo to match remote’s branch, and run ASV afterwards.
-pub(crate) async fn sync_repo_and_run<R>(req: &R) -> Result<RunResult>
+/// Sync repo to match remote’s branch, run ASV afterwards, and record the results in `history`.
+///
+/// If `log` is given, `asv`’s output is captured there.
+pub(crate) async fn sync_repo_and_run<R>(
+    req: &R,
+    history: &Arc<History>,
+    log: Option<&JobLog>,
+) -> Result<RunResult>
 where
     R: RunConfig + Send + Sync + Clone,
 {
     let (repo, config_ref) = {
         // clone data used in the thread
+        let owner = req.owner().to_owned();
         let repo = req.repo().to_owned();
         let config_ref = req.config_ref().map(str::to_owned);
-        tokio::task::spawn_blocking(move || sync_repo(&repo, config_ref.as_deref())).await??
+        let pr_head = req.pr_head();
+        tokio::task::spawn_blocking(move || {
+            sync_repo(&owner, &repo, config_ref.as_deref(), pr_head.as_ref())
+        })
+        .await??
     };
     tracing::info!("Synced config repo to {:?} @ {config_ref}", repo.path());
-    run_benchmark(repo, req.run_on()).await
+    let rr = run_benchmark(repo, req.run_on(), req.base_ref(), req.filter(), log).await?;
+    {
+        let owner = req.owner().to_owned();
+        let repo = req.repo().to_owned();
+        let on = req.run_on().to_owned();
+        let rr = rr.clone();
+        let history = history.clone();
+        let res =
+            tokio::task::spawn_blocking(move || record_history(&history, &owner, &repo, &rr, &on))
+                .await?;
+        // the run itself worked, so don’t fail it
+        if let Err(e) = res {
+            tracing::warn!("Failed to record results in history: {e:?}");
+        }
+    }
+    Ok(rr)
 }
 
-/// Create an `asv` command in the working directory
-pub(crate) fn asv_command(wd: &Path) -> Command {
-    let mut command = Command::new("asv");
-    command.current_dir(wd);
-    command
+/// Index the results for the commits run on in the [`History`].
+fn record_history(
+    history: &History,
+    owner: &str,
+    repo: &str,
+    rr: &RunResult,
+    on: &[String],
+) -> Result<()> {
+    let head = ["HEAD".to_owned()];
+    for name in if on.is_empty() { &head[..] } else { on } {
+        let commit = resolve_commit(&rr.wd, name)?;
+        let recorded = history.record(owner, repo, &rr.results_dir, &commit)?;
+        tracing::info!("Recorded {recorded} result file(s) for {commit} in history");
+    }
+    Ok(())
 }
 
-#[derive(Default, Debug, Clone)]
-pub(crate) struct AsvCompare {
-    wd: PathBuf,
-    left: String,
-    right: String,
-    only_changed: bool,
-    /// The envs to run on. If empty, run on all
-    envs: EnvSpecs,
+/// Where the HTML sites for all repositories’ results are published to.
+pub(crate) fn sites_dir() -> PathBuf {
+    DATA_DIR.join("sites")
 }
 
-impl AsvCompare {
-    pub fn new(wd: &Path, left: &str, right: &str) -> Self {
-        Self {
-            wd: wd.to_path_buf(),
-            left: left.into(),
-            right: right.into(),
-            only_changed: true,
-            envs: EnvSpecs::default(),
-        }
-    }
-    pub fn only_changed(&mut self, only_changed: bool) -> &mut Self {
-        self.only_changed = only_changed;
-        self
-    }
-    pub fn in_envs(&mut self, envs: EnvSpecs) -> &mut Self {
-        self.envs = envs;
-        self
-    }
-    fn command(&self) -> Command {
-        let mut command = asv_command(&self.wd);
-        command.arg("compare");
-        if self.only_changed {
-            command.arg("--only-changed");
-        }
-        command
-            .args(self.envs.args())
-            .args([&self.left, &self.right]);
-        command
-    }
-    pub async fn run(&self) -> Result<()> {
-        self.command()
-            .spawn()?
-            .wait()
-            .await?
-            .success()
-            .then_some(())
-            .ok_or_else(|| anyhow!("asv compare failed"))
-    }
-    pub async fn output(&self) -> Result<String> {
-        let Output {
-            stdout,
-            stderr,
-            status,
-        } = self.command().output().await?;
-        if status.code() == Some(0) {
-            return Ok(String::from_utf8(stdout)?);
-        }
-        Err(anyhow::anyhow!(
-            "asv compare exited with {status}:\n{}",
-            String::from_utf8_lossy(&stderr)
-        ))
+/// Where the HTML site for a repository’s results is published to, see [`publish`].
+pub(crate) fn site_dir(owner: &str, repo: &str) -> PathBuf {
+    sites_dir().join(owner).join(repo)
+}
+
+/// Render all results of a run’s repository into a static HTML site using `asv publish`.
+pub(crate) async fn publish(rr: &RunResult, html_dir: &Path, log: Option<&JobLog>) -> Result<()> {
+    tracing::info!("Publishing results to {}", html_dir.display());
+    let mut command = asv_command(&rr.wd);
+    command.arg("publish").arg("--html-dir").arg(html_dir);
+    let result = Process::spawn(&mut command, Phase::Publish, &rr.limits, log)?
+        .wait(rr.timeouts.for_phase(Phase::Publish))
+        .await?;
+    if !result.success() {
+        bail!("asv publish exited with {result}");
     }
+    Ok(())
+}
+
+/// Create an `asv` command in the working directory
+///
+/// The process gets killed when its handle is dropped, e.g. when a job gets cancelled.
+/// Spawn it as [`Process`] to also kill its subprocesses, and to apply timeouts and limits.
+pub(crate) fn asv_command(wd: &Path) -> Command {
+    let mut command = Command::new("asv");
+    command.current_dir(wd).kill_on_drop(true);
+    command
 }
 
 pub async fn resolve_env(wd: &Path) -> Result<EnvSpecs> {
@@ -122,6 +172,7 @@ pub async fn resolve_env(wd: &Path) -> Result<EnvSpecs> {
     let env_specs = resolve_env_from_stdout(
         Command::new("python")
             .current_dir(wd)
+            .kill_on_drop(true)
             .args(["-c", include_str!("resolve_env.py")]),
     )
     .await?;
@@ -136,18 +187,37 @@ async fn resolve_env_from_stdout(command: &mut Command) -> Result<Vec<String>> {
     Ok(parsed)
 }
 
-async fn run_benchmark(repo: git2::Repository, on: &[String]) -> Result<RunResult> {
-    let wd = {
+async fn run_benchmark(
+    repo: git2::Repository,
+    on: &[String],
+    base_ref: Option<&str>,
+    filter: &Filter,
+    log: Option<&JobLog>,
+) -> Result<RunResult> {
+    let (wd, config, repo_config) = {
         let on = on.to_owned();
-        tokio::task::spawn_blocking(move || fetch_configured_refs(&repo, &on)).await??
+        let base_ref = base_ref.map(str::to_owned);
+        tokio::task::spawn_blocking(move || fetch_configured_refs(&repo, &on, base_ref.as_deref()))
+            .await??
     };
+    // fail on an invalid config before spending time on running benchmarks
+    let thresholds = repo_config.thresholds(&config)?;
+    let envs = if filter.envs.is_empty() {
+        &repo_config.envs
+    } else {
+        &filter.envs
+    };
+    let bench = filter.bench.as_ref().or(repo_config.bench.as_ref());
+
+    let (timeouts, limits) = (&repo_config.timeouts, &repo_config.limits);
 
     tracing::info!("Re-discovering benchmarks in {}", wd.display());
-    let result = asv_command(&wd)
+    let mut command = asv_command(&wd);
+    command
         .args(["run", "--bench=just-discover"])
-        .args(on.iter().next_back().as_slice())
-        .spawn()?
-        .wait()
+        .args(on.iter().next_back().as_slice());
+    let result = Process::spawn(&mut command, Phase::Discover, limits, log)?
+        .wait(timeouts.for_phase(Phase::Discover))
         .await?;
     if result.code() != Some(0) {
         bail!("asv run --bench=just-discover exited with {result}");
@@ -155,25 +225,29 @@ async fn run_benchmark(repo: git2::Repository, on: &[String]) -> Result<RunResul
 
     tracing::info!("Running asv in {}", wd.display());
     let mut command = asv_command(&wd);
-    let env_specs = resolve_env(&wd).await?;
+    let env_specs = resolve_env(&wd).await?.matching(envs)?;
     command
-        .args(["run", "--launch-method=spawn"])
+        // samples allow testing changes for significance, see [`comparison`]
+        .args(["run", "--launch-method=spawn", "--record-samples"])
         .args(env_specs.args());
+    if let Some(bench) = bench {
+        command.arg(format!("--bench={bench}"));
+    }
+    if let Some(timeout) = timeouts.benchmark {
+        command.arg(format!("--attribute=timeout={timeout}"));
+    }
     // Adding .arg("--skip-existing-commits") would skip even if benchmarks changed
-    let mut child = if on.is_empty() {
-        command.spawn().context("failed to spawn `asv run`")?
+    let process = if on.is_empty() {
+        Process::spawn(&mut command, Phase::Run, limits, log)?
     } else {
-        let mut child = command
-            .stdin(Stdio::piped())
-            .arg("HASHFILE:-")
-            .spawn()
-            .context("failed to spawn `asv run HASHFILE:-`")?;
-        let mut stdin = child.stdin.take().context("no stdin")?;
+        command.stdin(Stdio::piped()).arg("HASHFILE:-");
+        let mut process = Process::spawn(&mut command, Phase::Run, limits, log)?;
+        let mut stdin = process.stdin().context("no stdin")?;
         stdin.write_all(on.join("\n").as_bytes()).await?;
         stdin.flush().await?;
-        child
+        process
     };
-    let result = child.wait().await?;
+    let result = process.wait(timeouts.for_phase(Phase::Run)).await?;
     let success = match result.code() {
         Some(0) => true,
         Some(2) => false,
@@ -182,58 +256,207 @@ async fn run_benchmark(repo: git2::Repository, on: &[String]) -> Result<RunResul
 
     Ok(RunResult {
         success,
+        results_dir: wd.join(&config.results_dir),
+        benchmark_dir: wd.join(&config.benchmark_dir),
+        thresholds,
         wd,
         env_specs,
+        timeouts: repo_config.timeouts,
+        limits: repo_config.limits,
+        comments: repo_config.comments,
     })
 }
 
+/// Where the asv config can be found, relative to the repository root
+pub(crate) const ASV_CONFIG_PATHS: [&str; 2] = ["benchmarks/asv.conf.json", "asv.conf.json"];
+
 #[derive(Deserialize)]
-struct AsvConfig {
+pub(crate) struct AsvConfig {
+    /// Branches to track results for
     #[serde(default = "default_branches")]
-    branches: Vec<String>,
+    pub branches: Vec<String>,
+    /// Directory results are stored in, relative to the config file
+    #[serde(default = "default_results_dir")]
+    pub results_dir: PathBuf,
+    /// Directory benchmarks are defined in, relative to the config file
+    #[serde(default = "default_benchmark_dir")]
+    pub benchmark_dir: PathBuf,
+    /// Relative change thresholds per benchmark name regex, see [`comparison::Thresholds`]
+    #[serde(default)]
+    pub regressions_thresholds: BTreeMap<String, f64>,
+}
+
+impl AsvConfig {
+    pub fn parse(json5: &str) -> Result<Self> {
+        serde_json5::from_str(json5).context("failed to parse asv config")
+    }
 }
 
 fn default_branches() -> Vec<String> {
     vec!["master".to_owned()]
 }
 
-fn fetch_configured_refs(repo: &git2::Repository, refs: &[String]) -> Result<PathBuf> {
-    let wd = {
-        let wd = repo.workdir().context("no workdir")?;
-        if wd.join("benchmarks").join("asv.conf.json").is_file() {
-            wd.join("benchmarks")
-        } else {
-            wd.to_path_buf()
-        }
-    };
-    // read ASV config
-    let file = File::open(wd.join("asv.conf.json"))?;
-    let mut buffer = String::new();
-    let mut reader = BufReader::new(file);
-    reader.read_to_string(&mut buffer)?;
-    let config: AsvConfig = serde_json5::from_str(&buffer)?;
+fn default_results_dir() -> PathBuf {
+    "results".into()
+}
 
-    {
-        let mut remote = repo.find_remote("origin")?;
-        let refs: Vec<String> = config
-            .branches
-            .iter()
-            .map(|b| format!("refs/heads/{b}"))
-            .chain(refs.iter().cloned())
-            .collect();
+fn default_benchmark_dir() -> PathBuf {
+    "benchmarks".into()
+}
+
+/// Resolve a commit-ish like a branch name to a full commit hash.
+pub(crate) fn resolve_commit(wd: &Path, name: &str) -> Result<String> {
+    let repo = git2::Repository::discover(wd)?;
+    let object = repo
+        .revparse_single(name)
+        .or_else(|_| repo.revparse_single(&format!("origin/{name}")))
+        .with_context(|| format!("failed to resolve {name}"))?;
+    let id = object.peel_to_commit()?.id();
+    Ok(id.to_string())
+}
+
+/// Fetch the refs to run on and the branches asv tracks, and read the configuration.
+///
+/// Commits that are already there (e.g. fetched from a fork) aren’t fetched again.
+///
+/// The repository configuration is read from `base_ref`, if given, so PRs can’t change it.
+fn fetch_configured_refs(
+    repo: &git2::Repository,
+    refs: &[String],
+    base_ref: Option<&str>,
+) -> Result<(PathBuf, AsvConfig, RepoConfig)> {
+    let mut remote = repo.find_remote("origin")?;
+    let refs: Vec<&String> = refs
+        .iter()
+        .filter(|r| local_commit(repo, r).is_none())
+        .collect();
+    if !refs.is_empty() {
         tracing::info!(
             "Fetching refs {refs:?} from remote {}",
             remote.name().unwrap_or("")
         );
         remote.fetch(&refs, None, None)?;
     }
-    Ok(wd)
+    let repo_config = RepoConfig::read(repo, base_ref)?;
+    let (wd, config) = read_asv_config(repo, &repo_config)?;
+
+    let branches: Vec<String> = config
+        .branches
+        .iter()
+        .map(|b| format!("refs/heads/{b}"))
+        .collect();
+    tracing::info!(
+        "Fetching branches {branches:?} from remote {}",
+        remote.name().unwrap_or("")
+    );
+    remote.fetch(&branches, None, None)?;
+    Ok((wd, config, repo_config))
+}
+
+/// Find and read the asv config in the working directory, returning the directory containing it.
+fn read_asv_config(
+    repo: &git2::Repository,
+    repo_config: &RepoConfig,
+) -> Result<(PathBuf, AsvConfig)> {
+    let config_path = {
+        let root = repo.workdir().context("no workdir")?;
+        repo_config
+            .asv_config_paths()
+            .iter()
+            .map(|p| root.join(p))
+            .find(|p| p.is_file())
+            .context("no asv.conf.json found")?
+    };
+    let wd = config_path.parent().context("no parent")?.to_path_buf();
+    let file = File::open(&config_path)?;
+    let mut buffer = String::new();
+    let mut reader = BufReader::new(file);
+    reader.read_to_string(&mut buffer)?;
+    Ok((wd, AsvConfig::parse(&buffer)?))
+}
+
+/// Compare results stored in the cached clone of a repository, without fetching or running anything.
+///
+/// `before` and `after` can be anything git resolves to a commit, like branch names.
+/// Only environments containing one of `envs` are compared, unless it is empty.
+pub(crate) fn compare_cached(
+    owner: &str,
+    repo: &str,
+    commits: [&str; 2],
+    envs: &[String],
+) -> Result<Comparison> {
+    compare_in(&CACHE_DIR, owner, repo, commits, envs)
+}
+
+/// [`compare_cached`] with the clones in `cache_dir`.
+fn compare_in(
+    cache_dir: &Path,
+    owner: &str,
+    repo: &str,
+    [before, after]: [&str; 2],
+    envs: &[String],
+) -> Result<Comparison> {
+    let path = cache_dir.join(owner).join(repo);
+    ensure!(
+        path.is_dir(),
+        "{owner}/{repo} has not been benchmarked here: no clone in {}",
+        path.display()
+    );
+    let (wd, config, repo_config) = read_cached_config(&path)?;
+    let thresholds = repo_config.thresholds(&config)?;
+    let results_dir = wd.join(&config.results_dir);
+    let mut commits = vec![];
+    for name in [before, after] {
+        let commit = resolve_commit(&wd, name)?;
+        ensure!(
+            !Results::load_all(&results_dir, &commit)?.is_empty(),
+            "no results for {name} ({commit}) in {}",
+            results_dir.display()
+        );
+        commits.push(commit);
+    }
+    let mut comparison =
+        Comparison::load(&results_dir, &commits[0], &commits[1], &[], &thresholds)?;
+    if !envs.is_empty() {
+        let available: BTreeSet<String> = comparison.rows.iter().map(|r| r.env.clone()).collect();
+        let EnvSpecs(matching) = EnvSpecs(available.into_iter().collect()).matching(envs)?;
+        comparison.rows.retain(|r| matching.contains(&r.env));
+    }
+    Ok(comparison)
+}
+
+/// The thresholds configured in the cached clone of a repository,
+/// like for runs on its default branch. The defaults if it hasn’t been cloned.
+pub(crate) fn cached_thresholds(owner: &str, repo: &str) -> Result<comparison::Thresholds> {
+    thresholds_in(&CACHE_DIR, owner, repo)
+}
+
+/// [`cached_thresholds`] with the clones in `cache_dir`.
+fn thresholds_in(cache_dir: &Path, owner: &str, repo: &str) -> Result<comparison::Thresholds> {
+    let path = cache_dir.join(owner).join(repo);
+    if !path.is_dir() {
+        return Ok(comparison::Thresholds::default());
+    }
+    let (_, config, repo_config) = read_cached_config(&path)?;
+    repo_config.thresholds(&config)
+}
+
+/// Read the configuration of the cached clone in `path`, without fetching anything.
+fn read_cached_config(path: &Path) -> Result<(PathBuf, AsvConfig, RepoConfig)> {
+    let git_repo = git2::Repository::open(path)?;
+    let repo_config = RepoConfig::read(&git_repo, None)?;
+    let (wd, config) = read_asv_config(&git_repo, &repo_config)?;
+    Ok((wd, config, repo_config))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::fs;
+
+    use crate::fixtures::{commit, ASV_BENCHMARKS, ASV_RESULTS_AFTER, ASV_RESULTS_BEFORE};
+
     #[tokio::test]
     async fn test_resolve_env() {
         let resolved_envs =
@@ -259,6 +482,140 @@ mod tests {
         assert!(format!("{e:?}").contains("invalid type: integer `1`, expected a string"));
     }
 
+    #[test]
+    fn test_env_specs_matching() {
+        let env_specs = EnvSpecs(vec!["py3.11-numpy1".to_owned(), "py3.12-numpy2".to_owned()]);
+        let matching = env_specs.clone().matching(&["py3.12".to_owned()]).unwrap();
+        assert_eq!(matching.0, vec!["py3.12-numpy2"]);
+        assert_eq!(env_specs.clone().matching(&[]).unwrap().0.len(), 2);
+        assert!(env_specs.matching(&["py2.7".to_owned()]).is_err());
+    }
+
+    /// Store `results` for `commit` in `envs`, as asv does.
+    fn write_results(results_dir: &Path, results: &str, commit: &str, envs: &[&str]) {
+        let machine_dir = results_dir.join("scvbench");
+        fs::create_dir_all(&machine_dir).unwrap();
+        // the fixtures contain `NaN`, so they aren’t valid JSON to edit
+        let fixture = Results::parse(results).unwrap();
+        for env in envs {
+            let json = results
+                .replace(&fixture.commit_hash, commit)
+                .replace(&fixture.env_name, env);
+            let path = machine_dir.join(format!("{}-{env}.json", &commit[..8]));
+            fs::write(path, json).unwrap();
+        }
+    }
+
+    /// Create a clone of scverse/demo in `cache_dir` with two commits, `HEAD~1` and `HEAD`.
+    ///
+    /// Returns the results directory and the commits.
+    fn setup_clone(cache_dir: &Path) -> (PathBuf, [String; 2]) {
+        let path = cache_dir.join("scverse").join("demo");
+        let repo = git2::Repository::init(&path).unwrap();
+        let files = [("benchmarks/asv.conf.json", r#"{"results_dir": "results"}"#)];
+        let before = commit(&repo, &files, "before", &[]);
+        let parent = repo.find_commit(before).unwrap();
+        let after = commit(&repo, &[], "after", &[&parent]);
+
+        let results_dir = path.join("benchmarks").join("results");
+        fs::create_dir(&results_dir).unwrap();
+        fs::write(results_dir.join("benchmarks.json"), ASV_BENCHMARKS).unwrap();
+        (results_dir, [before.to_string(), after.to_string()])
+    }
+
+    #[test]
+    fn test_compare_in() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        tmp_dir.used_by(|dir| {
+            let (results_dir, [before, after]) = setup_clone(dir);
+            let envs = ["virtualenv-py3.12", "virtualenv-py3.13"];
+            write_results(&results_dir, ASV_RESULTS_BEFORE, &before, &envs);
+            write_results(&results_dir, ASV_RESULTS_AFTER, &after, &envs);
+
+            let comparison = compare_in(dir, "scverse", "demo", ["HEAD~1", "HEAD"], &[]).unwrap();
+            assert_eq!((comparison.before, comparison.after), (before, after));
+            let compared: BTreeSet<_> = comparison.rows.iter().map(|r| r.env.as_str()).collect();
+            assert_eq!(compared, envs.into());
+
+            let filter = ["py3.13".to_owned()];
+            let comparison =
+                compare_in(dir, "scverse", "demo", ["HEAD~1", "HEAD"], &filter).unwrap();
+            assert!(!comparison.rows.is_empty());
+            assert!(comparison.rows.iter().all(|r| r.env == "virtualenv-py3.13"));
+
+            let filter = ["py2.7".to_owned()];
+            let err = compare_in(dir, "scverse", "demo", ["HEAD~1", "HEAD"], &filter).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                r#"No environment matches any of ["py2.7"]"#
+            );
+        });
+    }
+
+    #[test]
+    fn test_compare_in_missing_results() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        tmp_dir.used_by(|dir| {
+            let (results_dir, [before, after]) = setup_clone(dir);
+            write_results(
+                &results_dir,
+                ASV_RESULTS_BEFORE,
+                &before,
+                &["virtualenv-py3.12"],
+            );
+
+            let err = compare_in(dir, "scverse", "demo", ["HEAD~1", "HEAD"], &[]).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("no results for HEAD ({after}) in {}", results_dir.display())
+            );
+        });
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn test_thresholds_in() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        tmp_dir.used_by(|dir| {
+            let thresholds = thresholds_in(dir, "scverse", "demo").unwrap();
+            assert_eq!(
+                thresholds.for_benchmark("benchmarks.MemSuite.mem_list"),
+                0.1
+            );
+
+            setup_clone(dir);
+            fs::write(
+                dir.join("scverse/demo/benchmarks/asv.conf.json"),
+                r#"{"regressions_thresholds": {"benchmarks\\.Mem": 0.01}}"#,
+            )
+            .unwrap();
+            let thresholds = thresholds_in(dir, "scverse", "demo").unwrap();
+            assert_eq!(
+                thresholds.for_benchmark("benchmarks.MemSuite.mem_list"),
+                0.01
+            );
+            assert_eq!(
+                thresholds.for_benchmark("benchmarks.TimeSuite.time_iterkeys"),
+                0.1
+            );
+        });
+    }
+
+    #[test]
+    fn test_compare_in_missing_clone() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        tmp_dir.used_by(|dir| {
+            let err = compare_in(dir, "scverse", "other", ["HEAD~1", "HEAD"], &[]).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "scverse/other has not been benchmarked here: no clone in {}",
+                    dir.join("scverse").join("other").display()
+                )
+            );
+        });
+    }
+
     #[tokio::test]
     async fn test_resolve_env_crash_bad_command() {
         let e = resolve_env_from_stdout(&mut Command::new("echolllll"))
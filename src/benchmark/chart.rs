@@ -0,0 +1,222 @@
+//! SVG bar charts of comparisons.
+use std::fmt::Write;
+
+use super::comparison::{short, Change, Comparison, Measurement, Row};
+use super::results::Stats;
+
+/// Charts show at most this many benchmarks, the biggest changes
+const MAX_BARS: usize = 30;
+
+const WIDTH: f64 = 720.;
+/// Width of the benchmark names on the left
+const LABEL_WIDTH: f64 = 280.;
+/// Width of the ratios on the right
+const RATIO_WIDTH: f64 = 60.;
+const BAR_HEIGHT: f64 = 10.;
+/// Height of a benchmark’s group of two bars, including spacing
+const GROUP_HEIGHT: f64 = 2. * BAR_HEIGHT + 8.;
+/// Height of the legend on top
+const HEADER_HEIGHT: f64 = 30.;
+
+/// A benchmark’s measurements relative to its value before.
+struct Bar {
+    label: String,
+    /// `after / before`
+    ratio: f64,
+    /// Error of the values before and after, relative to the value before
+    errors: [f64; 2],
+    change: Change,
+}
+
+impl Bar {
+    fn new(row: &Row, multi_env: bool) -> Option<Self> {
+        let (
+            Some(Measurement::Value {
+                value: before,
+                stats: before_stats,
+            }),
+            Some(Measurement::Value {
+                value: after,
+                stats: after_stats,
+            }),
+        ) = (row.before, row.after)
+        else {
+            return None;
+        };
+        if before <= 0. {
+            return None;
+        }
+        let error = |stats: Option<Stats>| stats.map_or(0., |s| s.err() / before);
+        let label = if multi_env {
+            format!("{} ({})", row.name(), row.env)
+        } else {
+            row.name()
+        };
+        Some(Self {
+            label,
+            ratio: after / before,
+            errors: [error(before_stats), error(after_stats)],
+            change: row.change,
+        })
+    }
+}
+
+/// Render the changes in a comparison as before vs after bars,
+/// scaled so that each benchmark’s value before is 1.
+///
+/// Returns `None` if no measured benchmark changed significantly.
+pub(crate) fn svg(comparison: &Comparison) -> Option<String> {
+    let multi_env = comparison
+        .rows
+        .iter()
+        .any(|row| row.env != comparison.rows[0].env);
+    let mut bars: Vec<Bar> = comparison
+        .rows
+        .iter()
+        .filter(|row| row.change.is_change())
+        .filter_map(|row| Bar::new(row, multi_env))
+        .collect();
+    if bars.is_empty() {
+        return None;
+    }
+    bars.sort_by(|a, b| b.ratio.total_cmp(&a.ratio));
+    bars.truncate(MAX_BARS);
+
+    let max = bars
+        .iter()
+        .map(|bar| (bar.ratio + bar.errors[1]).max(1. + bar.errors[0]))
+        .fold(1., f64::max)
+        * 1.05;
+    let plot_width = WIDTH - LABEL_WIDTH - RATIO_WIDTH;
+    let x = |value: f64| LABEL_WIDTH + value.max(0.) / max * plot_width;
+    #[allow(clippy::cast_precision_loss)] // there are at most `MAX_BARS`
+    let height = HEADER_HEIGHT + bars.len() as f64 * GROUP_HEIGHT;
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{height}" viewBox="0 0 {WIDTH} {height}" font-family="sans-serif" font-size="11">"#
+    );
+    out.push_str(r##"<rect width="100%" height="100%" fill="#ffffff"/>"##);
+    out.push('\n');
+    let _ = writeln!(
+        out,
+        r##"<rect x="{LABEL_WIDTH}" y="8" width="10" height="10" fill="#959da5"/><text x="{}" y="17">before [{}]</text>"##,
+        LABEL_WIDTH + 14.,
+        escape(short(&comparison.before)),
+    );
+    let _ = writeln!(
+        out,
+        r##"<rect x="{}" y="8" width="10" height="10" fill="#0366d6"/><text x="{}" y="17">after [{}]</text>"##,
+        LABEL_WIDTH + 140.,
+        LABEL_WIDTH + 154.,
+        escape(short(&comparison.after)),
+    );
+    for (i, bar) in bars.iter().enumerate() {
+        #[allow(clippy::cast_precision_loss)]
+        let top = HEADER_HEIGHT + i as f64 * GROUP_HEIGHT;
+        let _ = writeln!(
+            out,
+            r#"<text x="{}" y="{}" text-anchor="end">{}</text>"#,
+            LABEL_WIDTH - 6.,
+            top + BAR_HEIGHT + 4.,
+            escape(&bar.label),
+        );
+        for (j, (value, color)) in [(1., "#959da5"), (bar.ratio, color(bar.change))]
+            .into_iter()
+            .enumerate()
+        {
+            #[allow(clippy::cast_precision_loss)]
+            let y = top + j as f64 * BAR_HEIGHT;
+            let _ = writeln!(
+                out,
+                r#"<rect x="{LABEL_WIDTH}" y="{y}" width="{:.1}" height="{}" fill="{color}"/>"#,
+                x(value) - LABEL_WIDTH,
+                BAR_HEIGHT - 1.,
+            );
+            let err = bar.errors[j];
+            if err > 0. {
+                let mid = y + (BAR_HEIGHT - 1.) / 2.;
+                let (x1, x2) = (x(value - err), x(value + err));
+                let _ = writeln!(
+                    out,
+                    r##"<path d="M{x1:.1} {mid}H{x2:.1}M{x1:.1} {:.1}V{:.1}M{x2:.1} {:.1}V{:.1}" stroke="#24292e" fill="none"/>"##,
+                    mid - 3.,
+                    mid + 3.,
+                    mid - 3.,
+                    mid + 3.,
+                );
+            }
+        }
+        let _ = writeln!(
+            out,
+            r#"<text x="{}" y="{}">{:.2}×</text>"#,
+            WIDTH - RATIO_WIDTH + 6.,
+            top + BAR_HEIGHT + 4.,
+            bar.ratio,
+        );
+    }
+    // each benchmark’s value before
+    let _ = writeln!(
+        out,
+        r##"<line x1="{0:.1}" y1="{HEADER_HEIGHT}" x2="{0:.1}" y2="{height}" stroke="#24292e" stroke-dasharray="2,2"/>"##,
+        x(1.),
+    );
+    out.push_str("</svg>\n");
+    Some(out)
+}
+
+fn color(change: Change) -> &'static str {
+    match change {
+        Change::Worse | Change::SlightlyWorse => "#d73a49",
+        Change::Better | Change::SlightlyBetter => "#28a745",
+        _ => "#0366d6",
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use crate::benchmark::comparison::Thresholds;
+    use crate::fixtures::fixture_comparison;
+
+    #[test]
+    fn test_svg() {
+        let svg = svg(&fixture_comparison()).unwrap();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.ends_with("</svg>\n"));
+        // slower, faster, and slightly slower; the failing benchmark has no value to show
+        assert_eq!(svg.matches("×</text>").count(), 3);
+        assert!(svg.contains(">2.50×</text>"));
+        assert!(svg.contains(">ParamSuite.time_sum(100, 'int')</text>"));
+        assert!(svg.contains("before [c1a9c5db]"));
+        // bars are sorted from the biggest regression
+        assert!(svg.find("TimeSuite.time_iterkeys") < svg.find("MemSuite.mem_list"));
+    }
+
+    #[test]
+    fn test_svg_unchanged() {
+        let comparison = Comparison::new(
+            ["a", "b"],
+            [&[], &[]],
+            &BTreeMap::new(),
+            &Thresholds::default(),
+        );
+        assert_eq!(svg(&comparison), None);
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a<b>&\"c\""), "a&lt;b&gt;&amp;&quot;c&quot;");
+    }
+}
@@ -0,0 +1,876 @@
+//! Compare asv results of two commits
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::history::{History, NoResults};
+
+use super::results::{load_benchmarks, BenchmarkInfo, BenchmarkResult, Results, Stats};
+use super::statistics::mann_whitney_u;
+
+/// Relative change above which a significant change is reported as such, like `asv compare`’s default `--factor=1.1`
+const DEFAULT_THRESHOLD: f64 = 0.1;
+/// Significance level for the Mann–Whitney U test
+const DEFAULT_ALPHA: f64 = 0.01;
+
+/// When to consider a change in a benchmark relevant.
+#[derive(Debug, Clone)]
+pub(crate) struct Thresholds {
+    /// Relative change thresholds for benchmarks whose names match the regex
+    per_benchmark: Vec<(Regex, f64)>,
+    pub default: f64,
+    /// Significance level for the Mann–Whitney U test
+    pub alpha: f64,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            per_benchmark: vec![],
+            default: DEFAULT_THRESHOLD,
+            alpha: DEFAULT_ALPHA,
+        }
+    }
+}
+
+impl Thresholds {
+    /// Create thresholds from asv’s `regressions_thresholds` config, mapping benchmark name regexes to thresholds.
+    pub fn new(regressions_thresholds: &BTreeMap<String, f64>) -> Result<Self> {
+        let per_benchmark = regressions_thresholds
+            .iter()
+            .map(|(pattern, &threshold)| {
+                // like Python’s `re.match`, which asv uses
+                let regex = Regex::new(&format!("^(?:{pattern})"))
+                    .with_context(|| format!("invalid benchmark name regex {pattern:?}"))?;
+                Ok((regex, threshold))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            per_benchmark,
+            ..Self::default()
+        })
+    }
+
+    /// The threshold for a benchmark. If multiple patterns match, the largest threshold wins.
+    pub fn for_benchmark(&self, name: &str) -> f64 {
+        self.per_benchmark
+            .iter()
+            .filter(|(regex, _)| regex.is_match(name))
+            .map(|&(_, threshold)| threshold)
+            .reduce(f64::max)
+            .unwrap_or(self.default)
+    }
+}
+
+/// Outcome of a benchmark for one commit.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub(crate) enum Measurement {
+    Failed,
+    Skipped,
+    Value { value: f64, stats: Option<Stats> },
+}
+
+impl Measurement {
+    fn from_result(result: &BenchmarkResult, i: usize) -> Option<Self> {
+        Some(match result.values.get(i)? {
+            None => Self::Failed,
+            Some(value) if value.is_nan() => Self::Skipped,
+            &Some(value) => Self::Value {
+                value,
+                stats: result.stats.get(i).copied().flatten(),
+            },
+        })
+    }
+}
+
+/// How a benchmark changed between two commits, ordered by importance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Change {
+    /// Worked before, fails now
+    Failed,
+    /// Significantly slower or bigger, by more than the threshold
+    Worse,
+    /// Significantly faster or smaller, by more than the threshold
+    Better,
+    /// Failed before, works now
+    Fixed,
+    /// Significantly slower or bigger, but within the threshold
+    SlightlyWorse,
+    /// Significantly faster or smaller, but within the threshold
+    SlightlyBetter,
+    /// No statistically significant change
+    Noise,
+    /// Benchmark changed, or was not run on both commits
+    Incomparable,
+}
+
+impl Change {
+    fn classify(
+        before: Option<Measurement>,
+        after: Option<Measurement>,
+        same_version: bool,
+        significant: bool,
+        threshold: f64,
+    ) -> Self {
+        use Measurement::{Failed, Value};
+
+        match (before, after) {
+            _ if !same_version => Self::Incomparable,
+            // also new benchmarks that fail right away
+            (Some(Value { .. }) | None, Some(Failed)) => Self::Failed,
+            (Some(Failed), Some(Value { .. })) => Self::Fixed,
+            (Some(Failed), Some(Failed)) => Self::Noise,
+            // e.g. a `track_*` benchmark starting at 0, there’s no ratio to compare
+            (Some(Value { value: 0., .. }), Some(Value { .. })) => Self::Incomparable,
+            (Some(Value { value: before, .. }), Some(Value { value: after, .. })) => {
+                let ratio = after / before;
+                if !significant {
+                    Self::Noise
+                } else if ratio > 1. + threshold {
+                    Self::Worse
+                } else if ratio < 1. / (1. + threshold) {
+                    Self::Better
+                } else if ratio > 1. {
+                    Self::SlightlyWorse
+                } else if ratio < 1. {
+                    Self::SlightlyBetter
+                } else {
+                    Self::Noise
+                }
+            }
+            _ => Self::Incomparable,
+        }
+    }
+
+    /// Section title for benchmarks with this change.
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::Failed => "Newly failing",
+            Self::Worse => "Significantly slower",
+            Self::Better => "Significantly faster",
+            Self::Fixed => "Fixed",
+            Self::SlightlyWorse => "Slightly slower",
+            Self::SlightlyBetter => "Slightly faster",
+            Self::Noise => "No significant change",
+            Self::Incomparable => "Not comparable",
+        }
+    }
+
+    /// Whether this is a change that should be reported.
+    pub fn is_change(self) -> bool {
+        !matches!(self, Self::Noise | Self::Incomparable)
+    }
+}
+
+/// Whether the difference in parameter combination `i` is statistically significant.
+///
+/// Uses the Mann–Whitney U test if enough samples were recorded, else checks if the 99% confidence intervals overlap.
+/// Without samples or statistics there’s no evidence either way. Like `asv compare`,
+/// such a difference is then considered significant, so only the threshold decides if it’s reported.
+fn is_significant(before: &BenchmarkResult, after: &BenchmarkResult, i: usize, alpha: f64) -> bool {
+    let samples = |r: &'_ BenchmarkResult| r.samples.get(i).and_then(Option::clone);
+    if let (Some(b), Some(a)) = (samples(before), samples(after)) {
+        if let Some(p) = mann_whitney_u(&b, &a) {
+            return p < alpha;
+        }
+    }
+    let stats = |r: &BenchmarkResult| r.stats.get(i).copied().flatten();
+    match (stats(before), stats(after)) {
+        (Some(b), Some(a)) => b.ci_99_b < a.ci_99_a || a.ci_99_b < b.ci_99_a,
+        // no evidence either way
+        _ => true,
+    }
+}
+
+/// Comparison of one parameter combination of a benchmark in one environment.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Row {
+    pub env: String,
+    pub benchmark: String,
+    /// Parameter values, e.g. `["10", "'int'"]`
+    pub params: Vec<String>,
+    pub unit: String,
+    pub before: Option<Measurement>,
+    pub after: Option<Measurement>,
+    pub change: Change,
+}
+
+impl Row {
+    /// `after / before`, if both were measured and `before` isn’t 0.
+    pub fn ratio(&self) -> Option<f64> {
+        match (self.before?, self.after?) {
+            (Measurement::Value { value: before, .. }, Measurement::Value { value: after, .. })
+                if before != 0. =>
+            {
+                Some(after / before)
+            }
+            _ => None,
+        }
+    }
+
+    /// Summary like `ParamSuite.time_sum(10, 'int'): 1.00ms → 1.50ms (1.50×) in py3.12`
+    pub fn describe(&self) -> String {
+        let ratio = self
+            .ratio()
+            .map_or_else(String::new, |r| format!(" ({r:.2}×)"));
+        format!(
+            "{}: {} → {}{ratio} in {}",
+            self.name(),
+            Display(self.before, &self.unit),
+            Display(self.after, &self.unit),
+            self.env,
+        )
+    }
+
+    /// Human readable measurements before and after, e.g. `["1.00ms", "1.50±0.10ms"]`
+    pub fn measurements(&self) -> [String; 2] {
+        [
+            Display(self.before, &self.unit).to_string(),
+            Display(self.after, &self.unit).to_string(),
+        ]
+    }
+
+    /// The class or module containing the benchmark, e.g. `ParamSuite`
+    pub fn suite(&self) -> &str {
+        let suite = self
+            .benchmark
+            .rsplit_once('.')
+            .map_or("", |(suite, _)| suite);
+        suite.strip_prefix("benchmarks.").unwrap_or(suite)
+    }
+
+    /// Benchmark name with parameters, e.g. `ParamSuite.time_sum(10, 'int')`
+    pub fn name(&self) -> String {
+        let name = self
+            .benchmark
+            .strip_prefix("benchmarks.")
+            .unwrap_or(&self.benchmark);
+        if self.params.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{name}({})", self.params.join(", "))
+        }
+    }
+}
+
+/// Structured comparison of two commits’ benchmark results.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Comparison {
+    pub before: String,
+    pub after: String,
+    /// Sorted by change, environment, and benchmark
+    pub rows: Vec<Row>,
+}
+
+impl Comparison {
+    /// Load and compare results from `results_dir`, restricted to `envs` unless empty.
+    pub fn load(
+        results_dir: &Path,
+        before: &str,
+        after: &str,
+        envs: &[String],
+        thresholds: &Thresholds,
+    ) -> Result<Self> {
+        let benchmarks = load_benchmarks(results_dir)?;
+        let keep = |r: &Results| envs.is_empty() || envs.contains(&r.env_name);
+        let before_results: Vec<_> = Results::load_all(results_dir, before)?
+            .into_iter()
+            .filter(keep)
+            .collect();
+        let after_results: Vec<_> = Results::load_all(results_dir, after)?
+            .into_iter()
+            .filter(keep)
+            .collect();
+        Ok(Self::new(
+            [before, after],
+            [&before_results, &after_results],
+            &benchmarks,
+            thresholds,
+        ))
+    }
+
+    /// Compare results stored in the [`History`], without running anything.
+    pub fn from_history(
+        history: &History,
+        owner: &str,
+        repo: &str,
+        [before, after]: [&str; 2],
+        thresholds: &Thresholds,
+    ) -> Result<Self> {
+        let (before_results, mut benchmarks) = history.load(owner, repo, before)?;
+        let (after_results, after_benchmarks) = history.load(owner, repo, after)?;
+        benchmarks.extend(after_benchmarks);
+        for (commit, results) in [(before, &before_results), (after, &after_results)] {
+            if results.is_empty() {
+                return Err(NoResults {
+                    owner: owner.to_owned(),
+                    repo: repo.to_owned(),
+                    commit: commit.to_owned(),
+                }
+                .into());
+            }
+        }
+        Ok(Self::new(
+            [
+                &before_results[0].commit_hash,
+                &after_results[0].commit_hash,
+            ],
+            [&before_results, &after_results],
+            &benchmarks,
+            thresholds,
+        ))
+    }
+
+    pub fn new(
+        [before, after]: [&str; 2],
+        [before_results, after_results]: [&[Results]; 2],
+        benchmarks: &BTreeMap<String, BenchmarkInfo>,
+        thresholds: &Thresholds,
+    ) -> Self {
+        let mut rows = vec![];
+        for after_env in after_results {
+            let before_env = before_results
+                .iter()
+                .find(|r| r.env_name == after_env.env_name && r.machine == after_env.machine);
+            for (name, after_result) in &after_env.benchmarks {
+                let before_result = before_env.and_then(|r| r.benchmarks.get(name));
+                let same_version = before_result.is_none_or(|b| b.version == after_result.version);
+                let threshold = thresholds.for_benchmark(name);
+                let unit = benchmarks
+                    .get(name)
+                    .map(|info| info.unit.clone())
+                    .unwrap_or_default();
+                for (i, params) in after_result.param_combinations().into_iter().enumerate() {
+                    let after = Measurement::from_result(after_result, i);
+                    let before = before_result.and_then(|b| Measurement::from_result(b, i));
+                    if matches!(
+                        (before, after),
+                        (
+                            Some(Measurement::Skipped) | None,
+                            Some(Measurement::Skipped) | None
+                        )
+                    ) {
+                        continue;
+                    }
+                    let significant = before_result
+                        .is_some_and(|b| is_significant(b, after_result, i, thresholds.alpha));
+                    rows.push(Row {
+                        env: after_env.env_name.clone(),
+                        benchmark: name.clone(),
+                        params: params.into_iter().map(str::to_owned).collect(),
+                        unit: unit.clone(),
+                        before,
+                        after,
+                        change: Change::classify(
+                            before,
+                            after,
+                            same_version,
+                            significant,
+                            threshold,
+                        ),
+                    });
+                }
+            }
+        }
+        rows.sort_by(|a, b| {
+            (a.change, &a.env, &a.benchmark).cmp(&(b.change, &b.env, &b.benchmark))
+        });
+        Self {
+            before: before.to_owned(),
+            after: after.to_owned(),
+            rows,
+        }
+    }
+
+    /// The part of the comparison for one environment.
+    pub fn for_env(&self, env: &str) -> Self {
+        Self {
+            before: self.before.clone(),
+            after: self.after.clone(),
+            rows: self.rows.iter().filter(|r| r.env == env).cloned().collect(),
+        }
+    }
+
+    /// Render as plain text table like `asv compare`, marking changes in the first column:
+    /// `!` newly failing, `+` slower, `-` faster, `x` fixed, `~` slightly slower or faster.
+    ///
+    /// With `only_changed`, benchmarks without significant change are left out.
+    pub fn text(&self, only_changed: bool) -> String {
+        let multi_env = self.rows.iter().any(|row| row.env != self.rows[0].env);
+        let mut table = vec![[
+            String::new(),
+            format!("Before [{}]", short(&self.before)),
+            format!("After [{}]", short(&self.after)),
+            "Ratio".to_owned(),
+            "Benchmark (Parameter)".to_owned(),
+        ]];
+        for row in &self.rows {
+            if only_changed && !row.change.is_change() {
+                continue;
+            }
+            let mark = match row.change {
+                Change::Failed => "!",
+                Change::Worse => "+",
+                Change::Better => "-",
+                Change::Fixed => "x",
+                Change::SlightlyWorse | Change::SlightlyBetter => "~",
+                Change::Noise | Change::Incomparable => "",
+            };
+            let ratio = row
+                .ratio()
+                .map_or_else(|| "n/a".to_owned(), |r| format!("{r:.2}"));
+            let [before, after] = row.measurements();
+            let name = if multi_env {
+                format!("{} [{}]", row.name(), row.env)
+            } else {
+                row.name()
+            };
+            table.push([mark.to_owned(), before, after, ratio, name]);
+        }
+        let mut widths = [0; 5];
+        for line in &table {
+            for (width, cell) in widths.iter_mut().zip(line) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+        let mut out = String::new();
+        for [mark, before, after, ratio, name] in &table {
+            let _ = writeln!(
+                out,
+                "{mark:<w0$}  {before:>w1$}  {after:>w2$}  {ratio:>w3$}  {name}",
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+                w3 = widths[3],
+            );
+        }
+        out
+    }
+
+    /// Render as markdown, one section per kind of change.
+    ///
+    /// With `only_changed`, only changes are shown in detail, and nothing if there are none.
+    pub fn markdown(&self, only_changed: bool) -> String {
+        let multi_env = self.rows.iter().any(|row| row.env != self.rows[0].env);
+        let mut out = String::new();
+        for rows in self.rows.chunk_by(|a, b| a.change == b.change) {
+            let change = rows[0].change;
+            if only_changed && !change.is_change() {
+                continue;
+            }
+            let _ = writeln!(out, "### {}\n", change.title());
+            if multi_env {
+                out.push_str("| Environment ");
+            }
+            let _ = writeln!(
+                out,
+                "| Before [{}] | After [{}] | Ratio | Benchmark (Parameter) |",
+                short(&self.before),
+                short(&self.after),
+            );
+            if multi_env {
+                out.push_str("|:------------");
+            }
+            out.push_str("|-----------:|----------:|------:|:----------------------|\n");
+            for row in rows {
+                if multi_env {
+                    let _ = write!(out, "| {} ", cell(&row.env));
+                }
+                let ratio = row
+                    .ratio()
+                    .map_or_else(|| "n/a".to_owned(), |r| format!("{r:.2}"));
+                let _ = writeln!(
+                    out,
+                    "| {} | {} | {ratio} | {} |",
+                    Display(row.before, &row.unit),
+                    Display(row.after, &row.unit),
+                    code_cell(&row.name()),
+                );
+            }
+            out.push('\n');
+        }
+        let unchanged = self.rows.len() - self.rows.iter().filter(|r| r.change.is_change()).count();
+        if only_changed && !out.is_empty() && unchanged > 0 {
+            let s = if unchanged == 1 { "" } else { "s" };
+            let _ = writeln!(
+                out,
+                "_{unchanged} other benchmark{s} did not change significantly._"
+            );
+        }
+        out.truncate(out.trim_end().len());
+        out
+    }
+}
+
+/// Escape `text` for a table cell: pipes would end the cell, line breaks the row.
+pub(crate) fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Format `text` as inline code in a table cell.
+///
+/// The code span is delimited by more backticks than `text` contains in a row,
+/// and pipes are escaped, as GitHub splits table rows at them even inside code spans.
+pub(crate) fn code_cell(text: &str) -> String {
+    let longest_run = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest_run + 1);
+    let pad = if text.starts_with('`') || text.ends_with('`') {
+        " "
+    } else {
+        ""
+    };
+    format!("{fence}{pad}{}{pad}{fence}", cell(text))
+}
+
+/// Abbreviate a commit hash to its first 8 characters.
+pub(crate) fn short(commit: &str) -> &str {
+    &commit[..commit.len().min(8)]
+}
+
+/// Human readable measurement, e.g. `1.23±0.05ms`
+struct Display<'a>(Option<Measurement>, &'a str);
+
+impl fmt::Display for Display<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            None => f.write_str("n/a"),
+            Some(Measurement::Failed) => f.write_str("failed"),
+            Some(Measurement::Skipped) => f.write_str("skipped"),
+            Some(Measurement::Value { value, stats }) => {
+                let (scale, suffix) = scale(value, self.1);
+                write!(f, "{}", significant(value / scale, 3))?;
+                if let Some(stats) = stats {
+                    write!(f, "±{}", significant(stats.err() / scale, 2))?;
+                }
+                f.write_str(&suffix)
+            }
+        }
+    }
+}
+
+/// Pick a scale and unit suffix to display `value` with.
+fn scale(value: f64, unit: &str) -> (f64, String) {
+    const TIME: [(f64, &str); 4] = [(1e-9, "ns"), (1e-6, "μs"), (1e-3, "ms"), (1., "s")];
+    const SIZE: [(f64, &str); 5] = [(1., ""), (1e3, "k"), (1e6, "M"), (1e9, "G"), (1e12, "T")];
+    let scales: &[(f64, &str)] = match unit {
+        "seconds" => &TIME,
+        "bytes" => &SIZE,
+        "" => return (1., String::new()),
+        unit => return (1., format!(" {unit}")),
+    };
+    let &(scale, suffix) = scales
+        .iter()
+        .rev()
+        .find(|(scale, _)| value.abs() >= *scale)
+        .unwrap_or(&scales[0]);
+    (scale, suffix.to_owned())
+}
+
+/// Format `value` with `digits` significant digits.
+fn significant(value: f64, digits: i32) -> String {
+    if value == 0. || !value.is_finite() {
+        return format!("{value}");
+    }
+    // round first, so e.g. 0.0999 gets formatted as 0.10 instead of 0.100
+    let rounded: f64 = format!("{value:.*e}", (digits - 1).unsigned_abs() as usize)
+        .parse()
+        .unwrap_or(value);
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let decimals = (digits - 1 - rounded.abs().log10().floor() as i32).max(0) as usize;
+    format!("{rounded:.decimals$}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    use super::super::results::parse_benchmarks;
+    use crate::fixtures::{
+        fixture_comparison, fixture_comparison_with, ASV_BENCHMARKS, ASV_RESULTS_AFTER,
+        ASV_RESULTS_BEFORE,
+    };
+
+    fn value(value: f64) -> Measurement {
+        Measurement::Value { value, stats: None }
+    }
+
+    #[test]
+    fn test_compare() {
+        let cmp = fixture_comparison();
+        let changes: Vec<_> = cmp.rows.iter().map(|r| (r.name(), r.change)).collect();
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    "ParamSuite.time_sum(10, 'float')".to_owned(),
+                    Change::Failed
+                ),
+                ("TimeSuite.time_iterkeys".to_owned(), Change::Worse),
+                ("ParamSuite.time_sum(100, 'int')".to_owned(), Change::Better),
+                ("MemSuite.mem_list".to_owned(), Change::SlightlyWorse),
+                ("ParamSuite.time_sum(10, 'int')".to_owned(), Change::Noise),
+            ]
+        );
+        assert!((cmp.rows[1].ratio().unwrap() - 2.5).abs() < 1e-9);
+        assert_eq!(cmp.rows[1].suite(), "TimeSuite");
+        assert_eq!(cmp.rows[1].measurements(), ["1.00±0.10μs", "2.50±0.10μs"]);
+    }
+
+    #[rstest]
+    #[case::new_and_failing(None, Some(Measurement::Failed), Change::Failed)]
+    #[case::new(None, Some(value(1.)), Change::Incomparable)]
+    #[case::from_zero(Some(value(0.)), Some(value(1.)), Change::Incomparable)]
+    #[case::zero(Some(value(0.)), Some(value(0.)), Change::Incomparable)]
+    #[case::to_zero(Some(value(1.)), Some(value(0.)), Change::Better)]
+    fn test_classify(
+        #[case] before: Option<Measurement>,
+        #[case] after: Option<Measurement>,
+        #[case] expected: Change,
+    ) {
+        let change = Change::classify(before, after, true, true, DEFAULT_THRESHOLD);
+        assert_eq!(change, expected);
+    }
+
+    #[test]
+    fn test_ratio_from_zero() {
+        let row = Row {
+            env: "py3.12".to_owned(),
+            benchmark: "benchmarks.track_count".to_owned(),
+            params: vec![],
+            unit: "unit".to_owned(),
+            before: Some(value(0.)),
+            after: Some(value(1.)),
+            change: Change::Incomparable,
+        };
+        assert_eq!(row.ratio(), None);
+    }
+
+    #[test]
+    fn test_from_history() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let history = tmp_dir.used_by(|p| History::open(&p.join("history.sqlite")).unwrap());
+        let benchmarks = parse_benchmarks(ASV_BENCHMARKS).unwrap();
+        for json in [ASV_RESULTS_BEFORE, ASV_RESULTS_AFTER] {
+            let results = Results::parse(json).unwrap();
+            history.insert("o", "r", &results, &benchmarks).unwrap();
+        }
+        let thresholds = Thresholds::default();
+        let cmp =
+            Comparison::from_history(&history, "o", "r", ["c1a9c5db", "0d41f859"], &thresholds)
+                .unwrap();
+        assert_eq!(
+            cmp.markdown(false),
+            fixture_comparison_with(&thresholds).markdown(false)
+        );
+
+        let err =
+            Comparison::from_history(&history, "o", "r", ["c1a9c5db", "ffffffff"], &thresholds)
+                .unwrap_err();
+        assert_eq!(err.to_string(), "no results for o/r@ffffffff in history");
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn test_compare_thresholds() {
+        let thresholds =
+            Thresholds::new(&BTreeMap::from([(r"benchmarks\.Mem".to_owned(), 0.01)])).unwrap();
+        assert_eq!(
+            thresholds.for_benchmark("benchmarks.MemSuite.mem_list"),
+            0.01
+        );
+        assert_eq!(
+            thresholds.for_benchmark("other.MemSuite"),
+            DEFAULT_THRESHOLD
+        );
+        let cmp = fixture_comparison_with(&thresholds);
+        let mem = cmp
+            .rows
+            .iter()
+            .find(|r| r.benchmark.contains("Mem"))
+            .unwrap();
+        assert_eq!(mem.change, Change::Worse);
+    }
+
+    #[test]
+    fn test_thresholds_invalid() {
+        let err = Thresholds::new(&BTreeMap::from([("(".to_owned(), 0.1)])).unwrap_err();
+        assert!(err.to_string().contains("invalid benchmark name regex"));
+    }
+
+    #[rstest]
+    #[case::separate_samples(Some(vec![1.0, 1.1, 1.2, 1.05]), Some(vec![2.0, 2.1, 2.2, 2.05]), 0.05, true)]
+    #[case::overlapping_samples(Some(vec![1.0, 1.1, 1.2, 1.05]), Some(vec![1.01, 1.12, 1.18, 1.04]), 0.05, false)]
+    #[case::too_few_samples_without_stats(Some(vec![1.0]), Some(vec![2.0]), 0.05, true)]
+    #[case::no_samples_or_stats(None, None, 0.05, true)]
+    fn test_is_significant(
+        #[case] before: Option<Vec<f64>>,
+        #[case] after: Option<Vec<f64>>,
+        #[case] alpha: f64,
+        #[case] expected: bool,
+    ) {
+        let result = |value, samples| BenchmarkResult {
+            values: vec![Some(value)],
+            samples: vec![samples],
+            ..BenchmarkResult::default()
+        };
+        assert_eq!(
+            is_significant(&result(1.0, before), &result(2.0, after), 0, alpha),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case::separate_cis((0.9, 1.1), (1.9, 2.1), true)]
+    #[case::overlapping_cis((0.5, 1.5), (1.4, 2.6), false)]
+    fn test_is_significant_ci(
+        #[case] before: (f64, f64),
+        #[case] after: (f64, f64),
+        #[case] expected: bool,
+    ) {
+        // without samples, the confidence intervals decide
+        let result = |value, (ci_99_a, ci_99_b)| BenchmarkResult {
+            values: vec![Some(value)],
+            stats: vec![Some(Stats {
+                ci_99_a,
+                ci_99_b,
+                q_25: None,
+                q_75: None,
+                number: None,
+                repeat: None,
+            })],
+            ..BenchmarkResult::default()
+        };
+        assert_eq!(
+            is_significant(&result(1.0, before), &result(2.0, after), 0, 0.05),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_markdown() {
+        let markdown = fixture_comparison().markdown(true);
+        assert_eq!(
+            markdown,
+            "\
+### Newly failing
+
+| Before [c1a9c5db] | After [0d41f859] | Ratio | Benchmark (Parameter) |
+|-----------:|----------:|------:|:----------------------|
+| 2.00±0.10ms | failed | n/a | `ParamSuite.time_sum(10, 'float')` |
+
+### Significantly slower
+
+| Before [c1a9c5db] | After [0d41f859] | Ratio | Benchmark (Parameter) |
+|-----------:|----------:|------:|:----------------------|
+| 1.00±0.10μs | 2.50±0.10μs | 2.50 | `TimeSuite.time_iterkeys` |
+
+### Significantly faster
+
+| Before [c1a9c5db] | After [0d41f859] | Ratio | Benchmark (Parameter) |
+|-----------:|----------:|------:|:----------------------|
+| 10.0±0.20ms | 5.00±0.10ms | 0.50 | `ParamSuite.time_sum(100, 'int')` |
+
+### Slightly slower
+
+| Before [c1a9c5db] | After [0d41f859] | Ratio | Benchmark (Parameter) |
+|-----------:|----------:|------:|:----------------------|
+| 1.02k | 1.08k | 1.05 | `MemSuite.mem_list` |
+
+_1 other benchmark did not change significantly._"
+        );
+        assert!(fixture_comparison()
+            .markdown(false)
+            .contains("### No significant change"));
+    }
+
+    #[test]
+    fn test_markdown_escaped() {
+        let mut cmp = fixture_comparison();
+        cmp.rows.truncate(1);
+        cmp.rows[0].params = vec!["'a|b'".to_owned(), "'`\n`'".to_owned()];
+        let markdown = cmp.markdown(true);
+        let row = markdown.lines().last().unwrap();
+        assert_eq!(
+            row,
+            "| 2.00±0.10ms | failed | n/a | ``ParamSuite.time_sum('a\\|b', '` `')`` |"
+        );
+    }
+
+    #[rstest]
+    #[case::plain("time_sum(10)", "`time_sum(10)`")]
+    #[case::pipe("time_or('a|b')", "`time_or('a\\|b')`")]
+    #[case::backtick("time_sum('`')", "``time_sum('`')``")]
+    #[case::edge_backtick("`a``", "``` `a`` ```")]
+    fn test_code_cell(#[case] text: &str, #[case] expected: &str) {
+        assert_eq!(code_cell(text), expected);
+    }
+
+    #[test]
+    fn test_for_env() {
+        let cmp = fixture_comparison();
+        assert_eq!(cmp.for_env("virtualenv-py3.12"), cmp);
+        let other = cmp.for_env("virtualenv-py3.11");
+        assert!(other.rows.is_empty());
+        assert_eq!(other.after, cmp.after);
+    }
+
+    #[test]
+    fn test_text() {
+        let text = fixture_comparison().text(true);
+        assert_eq!(
+            text,
+            "   Before [c1a9c5db]  After [0d41f859]  Ratio  Benchmark (Parameter)
+!        2.00±0.10ms            failed    n/a  ParamSuite.time_sum(10, 'float')
++        1.00±0.10μs       2.50±0.10μs   2.50  TimeSuite.time_iterkeys
+-        10.0±0.20ms       5.00±0.10ms   0.50  ParamSuite.time_sum(100, 'int')
+~              1.02k             1.08k   1.05  MemSuite.mem_list
+"
+        );
+        let all = fixture_comparison().text(false);
+        assert_eq!(all.lines().count(), 6);
+    }
+
+    #[test]
+    fn test_json() {
+        let json = serde_json::to_value(fixture_comparison()).unwrap();
+        let row = &json["rows"][1];
+        assert_eq!(row["benchmark"], "benchmarks.TimeSuite.time_iterkeys");
+        assert_eq!(row["change"], "worse");
+        assert_eq!(row["after"]["status"], "value");
+        assert_eq!(json["rows"][0]["after"]["status"], "failed");
+    }
+
+    #[test]
+    fn test_markdown_empty() {
+        let cmp = Comparison::new(
+            ["a", "b"],
+            [&[], &[]],
+            &BTreeMap::new(),
+            &Thresholds::default(),
+        );
+        assert_eq!(cmp.markdown(false), "");
+    }
+
+    #[rstest]
+    #[case(1.5e-6, "seconds", "1.50μs")]
+    #[case(0.25, "seconds", "250ms")]
+    #[case(12.0, "seconds", "12.0s")]
+    #[case(2_500_000., "bytes", "2.50M")]
+    #[case(3., "widgets", "3.00 widgets")]
+    fn test_display(#[case] value: f64, #[case] unit: &str, #[case] expected: &str) {
+        let measurement = Measurement::Value { value, stats: None };
+        assert_eq!(Display(Some(measurement), unit).to_string(), expected);
+    }
+}
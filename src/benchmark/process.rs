@@ -0,0 +1,404 @@
+//! Running `asv` processes with timeouts, resource limits, and captured output.
+//!
+//! Each process gets its own process group, so `asv`’s benchmark subprocesses
+//! are killed with it when it times out or its job gets cancelled.
+use std::fmt::Display;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::Instrument;
+
+use super::progress::Progress;
+use crate::repo_config::Limits;
+
+/// What a job is doing, used to report which step timed out and to label its output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Phase {
+    /// Discovering benchmarks
+    Discover,
+    /// Running benchmarks
+    Run,
+    /// Publishing the results as HTML site
+    Publish,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Discover => "discover",
+            Self::Run => "run",
+            Self::Publish => "publish",
+        })
+    }
+}
+
+/// A process that didn’t finish in time and was killed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TimedOut {
+    pub phase: Phase,
+    pub timeout: Duration,
+}
+
+impl Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Timed out in phase {} after {:?}",
+            self.phase, self.timeout
+        )
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// A file collecting the output of all `asv` processes of a job.
+///
+/// While benchmarks run, their [`Progress`] is parsed from the output.
+#[derive(Debug, Clone)]
+pub(crate) struct JobLog {
+    path: PathBuf,
+    file: Arc<Mutex<std::fs::File>>,
+    progress: Arc<watch::Sender<Option<Progress>>>,
+}
+
+impl JobLog {
+    /// Create an empty log file, replacing an existing one.
+    pub fn create(path: PathBuf) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = std::fs::File::create(&path)
+            .with_context(|| format!("failed to create log file {}", path.display()))?;
+        Ok(Self {
+            path,
+            file: Arc::new(Mutex::new(file)),
+            progress: Arc::new(watch::Sender::new(None)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get notified when `asv run` makes progress.
+    pub fn progress(&self) -> watch::Receiver<Option<Progress>> {
+        self.progress.subscribe()
+    }
+
+    /// Append a line to the log.
+    fn append(&self, line: &str) -> std::io::Result<()> {
+        let mut file = self.file.lock().expect("log lock poisoned");
+        // one write per line, so lines from stdout and stderr don’t get mixed up
+        file.write_all(format!("{line}\n").as_bytes())
+    }
+
+    /// Get the last `n` lines of the log, reading at most its last `max_bytes`.
+    ///
+    /// So the first line returned can be cut off at its start.
+    pub fn tail(&self, n: usize, max_bytes: u64) -> Result<String> {
+        let mut file = std::fs::File::open(&self.path)?;
+        let start = file.metadata()?.len().saturating_sub(max_bytes);
+        file.seek(SeekFrom::Start(start))?;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+        // don’t start in the middle of a character
+        let char_start = bytes
+            .iter()
+            .position(|&b| !is_utf8_continuation(b))
+            .unwrap_or(bytes.len());
+        let content = String::from_utf8_lossy(&bytes[char_start..]);
+        let lines: Vec<&str> = content.lines().collect();
+        Ok(lines[lines.len().saturating_sub(n)..].join("\n"))
+    }
+}
+
+/// Whether `byte` continues a multi-byte UTF-8 character.
+fn is_utf8_continuation(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+/// A spawned process, killed together with its process group when dropped before exiting.
+#[derive(Debug)]
+pub(crate) struct Process {
+    child: Child,
+    phase: Phase,
+    /// Tasks copying the process’ output into the log
+    forwarders: Vec<JoinHandle<()>>,
+}
+
+impl Process {
+    /// Spawn `command` in a new process group, applying `limits` to it and its subprocesses.
+    ///
+    /// If `log` is given, the process’ stdout and stderr are appended to it and traced,
+    /// otherwise they are inherited.
+    pub fn spawn(
+        command: &mut Command,
+        phase: Phase,
+        limits: &Limits,
+        log: Option<&JobLog>,
+    ) -> Result<Self> {
+        command.process_group(0);
+        if let Some(log) = log {
+            let cmd = command.as_std();
+            let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
+            log.append(&format!(
+                "==> [{phase}] {} {}",
+                cmd.get_program().to_string_lossy(),
+                args.join(" ")
+            ))?;
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+        let rlimits: Vec<_> = [
+            (libc::RLIMIT_AS, limits.memory.map(|mib| mib * 1024 * 1024)),
+            (libc::RLIMIT_CPU, limits.cpu),
+        ]
+        .into_iter()
+        .filter_map(|(resource, limit)| Some((resource, limit?)))
+        .collect();
+        if !rlimits.is_empty() {
+            // SAFETY: `setrlimit` is async-signal-safe and `rlimits` is allocated before forking.
+            unsafe {
+                command.pre_exec(move || {
+                    for &(resource, limit) in &rlimits {
+                        let rlimit = libc::rlimit {
+                            rlim_cur: limit,
+                            rlim_max: limit,
+                        };
+                        if libc::setrlimit(resource, &raw const rlimit) != 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                    }
+                    Ok(())
+                });
+            }
+        }
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("failed to spawn `asv` to {phase}"))?;
+        let mut forwarders = vec![];
+        if let Some(log) = log {
+            if let Some(stdout) = child.stdout.take() {
+                // `asv` reports progress on stdout
+                let progress = (phase == Phase::Run).then(|| Progress {
+                    percent: 0.,
+                    benchmark: None,
+                    started: Instant::now(),
+                });
+                forwarders.push(forward(stdout, "stdout", phase, log.clone(), progress));
+            }
+            if let Some(stderr) = child.stderr.take() {
+                forwarders.push(forward(stderr, "stderr", phase, log.clone(), None));
+            }
+        }
+        Ok(Self {
+            child,
+            phase,
+            forwarders,
+        })
+    }
+
+    /// Take the process’ stdin, if it was piped.
+    pub fn stdin(&mut self) -> Option<ChildStdin> {
+        self.child.stdin.take()
+    }
+
+    /// Wait for the process to exit, killing it and failing with [`TimedOut`] after `timeout`.
+    pub async fn wait(mut self, timeout: Duration) -> Result<ExitStatus> {
+        let Ok(status) = tokio::time::timeout(timeout, self.child.wait()).await else {
+            tracing::warn!("Killing `asv` after {timeout:?} in phase {}", self.phase);
+            // dropping `self` kills the process group
+            return Err(TimedOut {
+                phase: self.phase,
+                timeout,
+            }
+            .into());
+        };
+        // subprocesses that are still around might keep the pipes open
+        let forwarders = std::mem::take(&mut self.forwarders);
+        if tokio::time::timeout(
+            Duration::from_secs(5),
+            futures::future::join_all(forwarders),
+        )
+        .await
+        .is_err()
+        {
+            tracing::warn!("Output of `asv` in phase {} wasn’t closed", self.phase);
+        }
+        Ok(status?)
+    }
+}
+
+/// Copy lines from a process’ output into the log and trace them in the current span.
+///
+/// If `progress` is given, it is updated from the lines and published via [`JobLog::progress`].
+fn forward(
+    output: impl AsyncRead + Unpin + Send + 'static,
+    stream: &'static str,
+    phase: Phase,
+    log: JobLog,
+    mut progress: Option<Progress>,
+) -> JoinHandle<()> {
+    tokio::spawn(
+        async move {
+            let mut lines = BufReader::new(output).split(b'\n');
+            loop {
+                match lines.next_segment().await {
+                    Ok(Some(line)) => {
+                        let line = String::from_utf8_lossy(&line);
+                        let line = line.trim_end_matches('\r');
+                        tracing::info!(%phase, stream, "{line}");
+                        if let Err(e) = log.append(line) {
+                            tracing::warn!("Failed to write to {}: {e}", log.path.display());
+                        }
+                        if let Some(progress) = &mut progress {
+                            if progress.update(line) {
+                                log.progress.send_replace(Some(progress.clone()));
+                            }
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        tracing::warn!("Failed to read {stream} of `asv`: {e}");
+                        break;
+                    }
+                }
+            }
+        }
+        .in_current_span(),
+    )
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        // `id` is `None` once the process has been waited for
+        let Some(pid) = self.child.id().and_then(|pid| i32::try_from(pid).ok()) else {
+            return;
+        };
+        // SAFETY: the process group was created with the process’ ID, and the process wasn’t reaped,
+        // so the group can’t belong to anyone else.
+        unsafe {
+            libc::killpg(pid, libc::SIGKILL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_timeout_kills_group() {
+        let mut command = Command::new("sh");
+        // the subprocess would outlive its parent if only the parent was killed
+        command.args(["-c", "sleep 30 & echo $! ; wait"]);
+        command.stdout(std::process::Stdio::piped());
+        let mut process =
+            Process::spawn(&mut command, Phase::Run, &Limits::default(), None).unwrap();
+        let stdout = process.child.stdout.take().unwrap();
+        let err = process.wait(Duration::from_millis(500)).await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<TimedOut>(),
+            Some(&TimedOut {
+                phase: Phase::Run,
+                timeout: Duration::from_millis(500)
+            })
+        );
+        assert_eq!(err.to_string(), "Timed out in phase run after 500ms");
+
+        // stdout is closed once the subprocess is gone too
+        let mut pid = String::new();
+        tokio::io::AsyncReadExt::read_to_string(&mut { stdout }, &mut pid)
+            .await
+            .unwrap();
+        // a killed process might linger as zombie until it’s reaped
+        let gone = match std::fs::read_to_string(format!("/proc/{}/stat", pid.trim())) {
+            Ok(stat) => stat.contains(") Z "),
+            Err(e) => e.kind() == std::io::ErrorKind::NotFound,
+        };
+        assert!(gone, "subprocess {pid} survived");
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", "ulimit -t"])
+            .stdout(std::process::Stdio::piped());
+        let limits = Limits {
+            memory: None,
+            cpu: Some(42),
+        };
+        let mut process = Process::spawn(&mut command, Phase::Discover, &limits, None).unwrap();
+        let mut stdout = String::new();
+        tokio::io::AsyncReadExt::read_to_string(
+            &mut process.child.stdout.take().unwrap(),
+            &mut stdout,
+        )
+        .await
+        .unwrap();
+        let status = process.wait(Duration::from_secs(5)).await.unwrap();
+        assert!(status.success());
+        assert_eq!(stdout.trim(), "42");
+    }
+
+    #[tokio::test]
+    async fn test_log() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let log = JobLog::create(tmp_dir.as_path_untracked().join("logs").join("1.log")).unwrap();
+        for phase in [Phase::Discover, Phase::Run] {
+            let mut command = Command::new("sh");
+            command.args(["-c", &format!("echo {phase}; echo oops >&2")]);
+            let process =
+                Process::spawn(&mut command, phase, &Limits::default(), Some(&log)).unwrap();
+            let status = process.wait(Duration::from_secs(5)).await.unwrap();
+            assert!(status.success());
+        }
+        let content = std::fs::read_to_string(log.path()).unwrap();
+        assert!(content.starts_with("==> [discover] sh -c echo discover; echo oops >&2\n"));
+        assert_eq!(content.lines().filter(|l| *l == "oops").count(), 2);
+        assert_eq!(log.tail(2, 1024).unwrap().lines().count(), 2);
+        assert!(log.tail(3, 1024).unwrap().contains("==> [run]"));
+        assert_eq!(log.tail(100, 1024).unwrap(), content.trim_end());
+    }
+
+    #[test]
+    fn test_log_tail_bytes() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let log = JobLog::create(tmp_dir.as_path_untracked().join("1.log")).unwrap();
+        for i in 0..1000 {
+            log.append(&format!("line {i}: äöü")).unwrap();
+        }
+        // each line takes 17 bytes
+        assert_eq!(log.tail(100, 34).unwrap(), "line 998: äöü\nline 999: äöü");
+        assert_eq!(log.tail(1, 1 << 20).unwrap(), "line 999: äöü");
+        // starts within the “ö”
+        assert_eq!(log.tail(100, 4).unwrap(), "ü");
+    }
+
+    #[tokio::test]
+    async fn test_progress() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let log = JobLog::create(tmp_dir.as_path_untracked().join("1.log")).unwrap();
+        let mut progress = log.progress();
+        let mut command = Command::new("echo");
+        command.arg("[ 50.00%] ··· benchmarks.TimeSuite.time_keys  ok");
+        let process =
+            Process::spawn(&mut command, Phase::Run, &Limits::default(), Some(&log)).unwrap();
+        process.wait(Duration::from_secs(5)).await.unwrap();
+        assert!(progress.has_changed().unwrap());
+        let progress = progress.borrow_and_update().clone().unwrap();
+        assert!((progress.percent - 50.).abs() < f64::EPSILON);
+        assert_eq!(
+            progress.benchmark.as_deref(),
+            Some("benchmarks.TimeSuite.time_keys")
+        );
+    }
+}
@@ -0,0 +1,130 @@
+//! Progress of `asv run`, parsed from its output.
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+/// How far `asv run` got, from lines like `[ 42.00%] ··· benchmarks.TimeSuite.time_keys  ok`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Progress {
+    /// Percentage of benchmarks done
+    pub percent: f64,
+    /// The benchmark that is running or was run last
+    pub benchmark: Option<String>,
+    /// When `asv run` was started
+    pub started: Instant,
+}
+
+static PROGRESS_LINE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\[\s*(\d+(?:\.\d+)?)%\]\s+[·-]+\s*(.*)$").unwrap());
+
+impl Progress {
+    /// Update the progress from a line of output. Returns if it changed.
+    pub fn update(&mut self, line: &str) -> bool {
+        let Some(captures) = PROGRESS_LINE.captures(line) else {
+            return false;
+        };
+        let Ok(percent) = captures[1].parse::<f64>() else {
+            return false;
+        };
+        let benchmark = benchmark_name(&captures[2]).or_else(|| self.benchmark.clone());
+        let changed = self.benchmark != benchmark || (self.percent - percent).abs() > f64::EPSILON;
+        self.percent = percent;
+        self.benchmark = benchmark;
+        changed
+    }
+
+    /// Estimated time until `asv run` is done, extrapolating from the time elapsed so far.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.percent <= 0. {
+            return None;
+        }
+        let elapsed = self.started.elapsed().as_secs_f64();
+        Some(Duration::from_secs_f64(
+            elapsed * (100. - self.percent).max(0.) / self.percent,
+        ))
+    }
+}
+
+/// Find the benchmark name in e.g. `Running (benchmarks.X.time_y--).` or `benchmarks.X.time_y  ok`
+fn benchmark_name(text: &str) -> Option<String> {
+    let text = text.strip_prefix("Running (").unwrap_or(text);
+    let name = text
+        .split_whitespace()
+        .next()?
+        .trim_end_matches(['-', ')', '.']);
+    let is_name = name.contains('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '.'));
+    is_name.then(|| name.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(
+        "[ 42.00%] ··· benchmarks.TimeSuite.time_keys      1.23μs",
+        42.,
+        Some("benchmarks.TimeSuite.time_keys")
+    )]
+    #[case(
+        "[ 12.50%] ··· Running (benchmarks.TimeSuite.time_iterkeys--).",
+        12.5,
+        Some("benchmarks.TimeSuite.time_iterkeys")
+    )]
+    #[case(
+        "[100.00%] ··· preprocessing.time_pca     failed",
+        100.,
+        Some("preprocessing.time_pca")
+    )]
+    // lines without a benchmark keep the previous one
+    #[case(
+        "[  0.00%] · For scanpy commit 0d41f859 <main>:",
+        0.,
+        Some("prev.bench")
+    )]
+    #[case("[ 50.00%] ··· ======== =======", 50., Some("prev.bench"))]
+    fn test_update(#[case] line: &str, #[case] percent: f64, #[case] benchmark: Option<&str>) {
+        let mut progress = Progress {
+            percent: 1.,
+            benchmark: Some("prev.bench".to_owned()),
+            started: Instant::now(),
+        };
+        assert!(progress.update(line));
+        assert!((progress.percent - percent).abs() < f64::EPSILON);
+        assert_eq!(progress.benchmark.as_deref(), benchmark);
+    }
+
+    #[rstest]
+    #[case("· Creating environments")]
+    #[case("Traceback (most recent call last):")]
+    #[case("")]
+    fn test_update_other(#[case] line: &str) {
+        let mut progress = Progress {
+            percent: 0.,
+            benchmark: None,
+            started: Instant::now(),
+        };
+        assert!(!progress.update(line));
+        assert_eq!(progress.benchmark, None);
+    }
+
+    #[test]
+    fn test_eta() {
+        let started = Instant::now().checked_sub(Duration::from_mins(1)).unwrap();
+        let mut progress = Progress {
+            percent: 0.,
+            benchmark: None,
+            started,
+        };
+        assert_eq!(progress.eta(), None);
+        progress.percent = 25.;
+        let eta = progress.eta().unwrap().as_secs_f64();
+        assert!((179. ..190.).contains(&eta), "{eta}");
+    }
+}
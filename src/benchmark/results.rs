@@ -0,0 +1,366 @@
+//! Read asv’s result files, see <https://asv.readthedocs.io/en/latest/dev.html#benchmark-suite-layout-and-file-formats>
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// A JSON value that, unlike [`serde_json::Value`], keeps `NaN`s intact.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Cell {
+    Null,
+    Number(f64),
+    Text(String),
+    List(Vec<Cell>),
+}
+
+impl Cell {
+    fn into_list(self) -> Result<Vec<Cell>> {
+        match self {
+            Self::List(list) => Ok(list),
+            Self::Null => Ok(vec![]),
+            other => bail!("expected list, got {other:?}"),
+        }
+    }
+    fn into_number(self) -> Result<Option<f64>> {
+        match self {
+            Self::Number(n) => Ok(Some(n)),
+            Self::Null => Ok(None),
+            other => bail!("expected number, got {other:?}"),
+        }
+    }
+    fn into_text(self) -> Result<Option<String>> {
+        match self {
+            Self::Text(s) => Ok(Some(s)),
+            Self::Null => Ok(None),
+            other => bail!("expected string, got {other:?}"),
+        }
+    }
+    fn into_numbers(self) -> Result<Vec<Option<f64>>> {
+        self.into_list()?
+            .into_iter()
+            .map(Cell::into_number)
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct RawResults {
+    commit_hash: String,
+    /// Commit date in milliseconds since the epoch
+    #[serde(default)]
+    date: Option<i64>,
+    env_name: String,
+    #[serde(default)]
+    params: HashMap<String, Cell>,
+    result_columns: Vec<String>,
+    results: BTreeMap<String, Vec<Cell>>,
+    version: u32,
+}
+
+/// Statistics for one parameter combination of a benchmark.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Stats {
+    /// Lower bound of the 99% confidence interval
+    pub ci_99_a: f64,
+    /// Upper bound of the 99% confidence interval
+    pub ci_99_b: f64,
+    pub q_25: Option<f64>,
+    pub q_75: Option<f64>,
+    pub number: Option<f64>,
+    pub repeat: Option<f64>,
+}
+
+impl Stats {
+    /// Half width of the confidence interval, as shown in `value±err`.
+    pub fn err(&self) -> f64 {
+        (self.ci_99_b - self.ci_99_a) / 2.
+    }
+}
+
+/// Measurements of one benchmark in one environment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct BenchmarkResult {
+    /// Parameter values, e.g. `[["1", "2"], ["'a'"]]`
+    pub params: Vec<Vec<String>>,
+    /// One value per combination of [`params`](Self::params). `None` means failed, `NaN` skipped.
+    pub values: Vec<Option<f64>>,
+    /// One entry per combination of [`params`](Self::params), if recorded.
+    pub stats: Vec<Option<Stats>>,
+    /// Raw samples per combination of [`params`](Self::params), if recorded.
+    pub samples: Vec<Option<Vec<f64>>>,
+    /// Hash of the benchmark code, changes when the benchmark changes.
+    pub version: Option<String>,
+}
+
+impl BenchmarkResult {
+    /// All combinations of parameter values, in the same order as [`values`](Self::values).
+    pub fn param_combinations(&self) -> Vec<Vec<&str>> {
+        self.params
+            .iter()
+            .fold(vec![vec![]], |combinations, values| {
+                combinations
+                    .iter()
+                    .flat_map(|prefix| {
+                        values.iter().map(move |value| {
+                            let mut combination = prefix.clone();
+                            combination.push(value.as_str());
+                            combination
+                        })
+                    })
+                    .collect()
+            })
+    }
+
+    fn from_columns(columns: &[String], cells: Vec<Cell>) -> Result<Self> {
+        let mut result = Self::default();
+        let mut stats: HashMap<&str, Vec<Option<f64>>> = HashMap::new();
+        for (column, cell) in columns.iter().zip(cells) {
+            match column.as_str() {
+                "result" => result.values = cell.into_numbers()?,
+                "params" => {
+                    result.params = cell
+                        .into_list()?
+                        .into_iter()
+                        .map(|values| {
+                            values
+                                .into_list()?
+                                .into_iter()
+                                .map(|v| Ok(v.into_text()?.unwrap_or_default()))
+                                .collect()
+                        })
+                        .collect::<Result<_>>()?;
+                }
+                "version" => result.version = cell.into_text()?,
+                "samples" => {
+                    result.samples = cell
+                        .into_list()?
+                        .into_iter()
+                        .map(|s| match s {
+                            Cell::Null => Ok(None),
+                            s => Ok(Some(s.into_numbers()?.into_iter().flatten().collect())),
+                        })
+                        .collect::<Result<_>>()?;
+                }
+                column => {
+                    if let Some(stat) = column.strip_prefix("stats_") {
+                        stats.insert(stat, cell.into_numbers()?);
+                    }
+                }
+            }
+        }
+        let stat = |name: &str, i: usize| stats.get(name).and_then(|s| s.get(i).copied().flatten());
+        result.stats = (0..result.values.len())
+            .map(|i| {
+                Some(Stats {
+                    ci_99_a: stat("ci_99_a", i)?,
+                    ci_99_b: stat("ci_99_b", i)?,
+                    q_25: stat("q_25", i),
+                    q_75: stat("q_75", i),
+                    number: stat("number", i),
+                    repeat: stat("repeat", i),
+                })
+            })
+            .collect();
+        Ok(result)
+    }
+}
+
+/// Contents of one `<results_dir>/<machine>/<commit>-<env>.json` file.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Results {
+    pub commit_hash: String,
+    /// When the commit was made
+    pub date: Option<DateTime<Utc>>,
+    pub env_name: String,
+    pub machine: String,
+    pub benchmarks: BTreeMap<String, BenchmarkResult>,
+}
+
+impl Results {
+    pub fn parse(json: &str) -> Result<Self> {
+        let raw: RawResults = serde_json5::from_str(json)?;
+        if raw.version != 2 {
+            bail!("unsupported asv results version {}", raw.version);
+        }
+        let machine = match raw.params.get("machine") {
+            Some(Cell::Text(machine)) => machine.clone(),
+            _ => String::new(),
+        };
+        let benchmarks = raw
+            .results
+            .into_iter()
+            .map(|(name, cells)| {
+                let result = BenchmarkResult::from_columns(&raw.result_columns, cells)
+                    .with_context(|| format!("invalid result for {name}"))?;
+                Ok((name, result))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            commit_hash: raw.commit_hash,
+            date: raw.date.and_then(DateTime::from_timestamp_millis),
+            env_name: raw.env_name,
+            machine,
+            benchmarks,
+        })
+    }
+
+    /// Load results for a commit from all machines and environments.
+    pub fn load_all(results_dir: &Path, commit: &str) -> Result<Vec<Self>> {
+        let prefix = format!("{}-", &commit[..commit.len().min(8)]);
+        let mut all = vec![];
+        for path in result_files(results_dir)? {
+            let matches_commit = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with(&prefix));
+            if !matches_commit {
+                continue;
+            }
+            let json = fs::read_to_string(&path)?;
+            let results =
+                Self::parse(&json).with_context(|| format!("failed to read {}", path.display()))?;
+            if results.commit_hash.starts_with(commit) {
+                all.push(results);
+            }
+        }
+        Ok(all)
+    }
+}
+
+/// All `<machine>/*.json` files in the results directory.
+fn result_files(results_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    if !results_dir.is_dir() {
+        return Ok(files);
+    }
+    for machine_dir in fs::read_dir(results_dir)? {
+        let machine_dir = machine_dir?.path();
+        if !machine_dir.is_dir() {
+            continue;
+        }
+        for file in fs::read_dir(&machine_dir)? {
+            let file = file?.path();
+            let is_results = file.extension().is_some_and(|e| e == "json")
+                && file.file_name().is_some_and(|n| n != "machine.json");
+            if is_results {
+                files.push(file);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Metadata about a benchmark from `<results_dir>/benchmarks.json`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct BenchmarkInfo {
+    pub name: String,
+    #[serde(default)]
+    pub code: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub unit: String,
+    #[serde(default)]
+    pub param_names: Vec<String>,
+}
+
+/// Read `<results_dir>/benchmarks.json`.
+pub(crate) fn load_benchmarks(results_dir: &Path) -> Result<BTreeMap<String, BenchmarkInfo>> {
+    let path = results_dir.join("benchmarks.json");
+    let json =
+        fs::read_to_string(&path).with_context(|| format!("failed to read {}", path.display()))?;
+    parse_benchmarks(&json)
+}
+
+/// Parse the contents of a `benchmarks.json` file.
+pub(crate) fn parse_benchmarks(json: &str) -> Result<BTreeMap<String, BenchmarkInfo>> {
+    let raw: BTreeMap<String, serde_json::Value> = serde_json::from_str(json)?;
+    raw.into_iter()
+        // skip the `"version": 2` entry
+        .filter(|(_, value)| value.is_object())
+        .map(|(name, value)| {
+            let info = serde_json::from_value(value)
+                .with_context(|| format!("invalid benchmark info for {name}"))?;
+            Ok((name, info))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixtures::{ASV_BENCHMARKS, ASV_RESULTS_AFTER, ASV_RESULTS_BEFORE};
+
+    #[test]
+    fn test_parse_results() {
+        let results = Results::parse(ASV_RESULTS_AFTER).unwrap();
+        assert_eq!(results.machine, "scvbench");
+        assert_eq!(results.env_name, "virtualenv-py3.12");
+        assert!(results.date.is_some());
+        let simple = &results.benchmarks["benchmarks.TimeSuite.time_iterkeys"];
+        assert_eq!(simple.values, vec![Some(2.5e-6)]);
+        assert_eq!(simple.param_combinations(), vec![Vec::<&str>::new()]);
+        let stats = simple.stats[0].unwrap();
+        assert_eq!((stats.ci_99_a, stats.ci_99_b), (2.4e-6, 2.6e-6));
+
+        let params = &results.benchmarks["benchmarks.ParamSuite.time_sum"];
+        assert_eq!(
+            params.param_combinations(),
+            vec![
+                vec!["10", "'int'"],
+                vec!["10", "'float'"],
+                vec!["100", "'int'"],
+                vec!["100", "'float'"]
+            ]
+        );
+        assert_eq!(params.values[1], None); // failed
+        assert!(params.values[3].unwrap().is_nan()); // skipped
+        assert_eq!(params.stats[1], None);
+    }
+
+    #[test]
+    fn test_load_all() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let loaded = tmp_dir.used_by(|p| {
+            fs::create_dir(p.join("scvbench")).unwrap();
+            fs::write(p.join("scvbench/machine.json"), "{}").unwrap();
+            fs::write(
+                p.join("scvbench/c1a9c5db-virtualenv-py3.12.json"),
+                ASV_RESULTS_BEFORE,
+            )
+            .unwrap();
+            fs::write(
+                p.join("scvbench/0d41f859-virtualenv-py3.12.json"),
+                ASV_RESULTS_AFTER,
+            )
+            .unwrap();
+            Results::load_all(p, "c1a9c5db18e66ca45c0ade2c619f1f7c6dbad375").unwrap()
+        });
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(
+            loaded[0].commit_hash,
+            "c1a9c5db18e66ca45c0ade2c619f1f7c6dbad375"
+        );
+    }
+
+    #[test]
+    fn test_load_benchmarks() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let benchmarks = tmp_dir.used_by(|p| {
+            fs::write(p.join("benchmarks.json"), ASV_BENCHMARKS).unwrap();
+            load_benchmarks(p).unwrap()
+        });
+        let info = &benchmarks["benchmarks.MemSuite.mem_list"];
+        assert_eq!(
+            (info.kind.as_str(), info.unit.as_str()),
+            ("memory", "bytes")
+        );
+        assert_eq!(benchmarks.len(), 3);
+    }
+}
@@ -0,0 +1,153 @@
+//! Finding where benchmarks are defined.
+use std::path::{Path, PathBuf};
+
+/// A line in a file of the repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Location {
+    /// Path relative to the repository root, with `/` as separator
+    pub path: String,
+    /// 1-based line number
+    pub line: u32,
+}
+
+/// Find where a benchmark like `benchmarks.TimeSuite.time_keys` is defined in a commit.
+///
+/// `benchmark_dir` is asv’s `benchmark_dir`, relative to the repository root.
+/// Benchmark names start with the module path relative to it,
+/// followed by the class (if any) and function name.
+pub(crate) fn locate(
+    repo: &git2::Repository,
+    commit: &str,
+    benchmark_dir: &Path,
+    name: &str,
+) -> Option<Location> {
+    let tree = repo.revparse_single(commit).ok()?.peel_to_tree().ok()?;
+    let parts: Vec<&str> = name.split('.').collect();
+    // prefer the longest module path, e.g. `sub/mod.py` over `sub/__init__.py`
+    for module_len in (1..parts.len()).rev() {
+        let (module, rest) = parts.split_at(module_len);
+        let (class, func) = match rest {
+            [func] => (None, *func),
+            [class, func] => (Some(*class), *func),
+            _ => continue,
+        };
+        let module_path: PathBuf = module.iter().collect();
+        for path in [
+            benchmark_dir.join(&module_path).with_extension("py"),
+            benchmark_dir.join(&module_path).join("__init__.py"),
+        ] {
+            let Ok(entry) = tree.get_path(&path) else {
+                continue;
+            };
+            let Ok(blob) = entry.to_object(repo).and_then(|o| o.peel_to_blob()) else {
+                continue;
+            };
+            let source = String::from_utf8_lossy(blob.content());
+            if let Some(line) = find_line(&source, class, func) {
+                let path = path.to_str()?.replace(std::path::MAIN_SEPARATOR, "/");
+                return Some(Location { path, line });
+            }
+        }
+    }
+    None
+}
+
+/// Find the 1-based line where `func` is defined, inside of `class` if given.
+fn find_line(source: &str, class: Option<&str>, func: &str) -> Option<u32> {
+    let defines = |line: &str, keyword: &str, name: &str| {
+        line.strip_prefix(keyword)
+            .and_then(|rest| rest.strip_prefix(name))
+            .is_some_and(|rest| rest.starts_with(['(', ':']))
+    };
+    let is_func = |(_, line): &(usize, &str)| {
+        let line = line.trim_start();
+        defines(line.strip_prefix("async ").unwrap_or(line), "def ", func)
+    };
+    let mut lines = source.lines().enumerate();
+    let (index, _) = if let Some(class) = class {
+        lines.find(|(_, line)| defines(line, "class ", class))?;
+        // the class body ends at the next unindented line
+        lines
+            .take_while(|(_, line)| line.trim().is_empty() || line.starts_with([' ', '\t', '#']))
+            .find(is_func)?
+    } else {
+        lines.find(|(_, line)| defines(line, "def ", func))?
+    };
+    u32::try_from(index + 1).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    use crate::fixtures::commit;
+
+    const SOURCE: &str = "\
+import numpy as np
+
+
+def time_top_level():
+    pass
+
+
+class TimeSuite:
+    def setup(self):
+        self.d = {}
+
+    def time_keys(self):
+        self.d.keys()
+
+
+class MemSuite:
+    async def mem_list(self):
+        return [0] * 256
+";
+
+    #[rstest]
+    #[case(None, "time_top_level", Some(4))]
+    #[case(Some("TimeSuite"), "time_keys", Some(12))]
+    #[case(Some("MemSuite"), "mem_list", Some(17))]
+    #[case(Some("MemSuite"), "time_keys", None)]
+    #[case(Some("TimeSuite"), "mem_list", None)]
+    #[case(Some("Missing"), "time_keys", None)]
+    #[case(None, "time_top", None)]
+    fn test_find_line(
+        #[case] class: Option<&str>,
+        #[case] func: &str,
+        #[case] expected: Option<u32>,
+    ) {
+        assert_eq!(find_line(SOURCE, class, func), expected);
+    }
+
+    #[test]
+    fn test_locate() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        tmp_dir.used_by(|dir| {
+            let repo = git2::Repository::init(dir).unwrap();
+            let files = [
+                ("benchmarks/benchmarks.py", SOURCE),
+                ("benchmarks/sub/__init__.py", "def time_init():\n    pass\n"),
+            ];
+            let commit = commit(&repo, &files, "benchmarks", &[]).to_string();
+
+            let locate = |name| locate(&repo, &commit, Path::new("benchmarks"), name);
+            assert_eq!(
+                locate("benchmarks.TimeSuite.time_keys"),
+                Some(Location {
+                    path: "benchmarks/benchmarks.py".to_owned(),
+                    line: 12
+                })
+            );
+            assert_eq!(
+                locate("sub.time_init"),
+                Some(Location {
+                    path: "benchmarks/sub/__init__.py".to_owned(),
+                    line: 1
+                })
+            );
+            assert_eq!(locate("missing.TimeSuite.time_keys"), None);
+        });
+    }
+}
@@ -0,0 +1,102 @@
+//! Statistical tests for benchmark samples
+
+/// Two-sided p-value of the Mann–Whitney U test, using the normal approximation with tie correction.
+///
+/// Returns `None` if there are too few samples to test.
+pub(crate) fn mann_whitney_u(before: &[f64], after: &[f64]) -> Option<f64> {
+    if before.len() < 2 || after.len() < 2 {
+        return None;
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let (n_before, n_after) = (before.len() as f64, after.len() as f64);
+    let n_total = n_before + n_after;
+
+    // rank all samples, averaging the ranks of ties
+    let mut all: Vec<(f64, bool)> = before
+        .iter()
+        .map(|&x| (x, true))
+        .chain(after.iter().map(|&x| (x, false)))
+        .collect();
+    all.sort_by(|x, y| x.0.total_cmp(&y.0));
+    let mut rank_sum_before = 0.;
+    let mut tie_term = 0.;
+    let mut start = 0;
+    while start < all.len() {
+        let value = all[start].0;
+        let end = start
+            + all[start..]
+                .iter()
+                .take_while(|x| x.0.total_cmp(&value).is_eq())
+                .count();
+        #[allow(clippy::cast_precision_loss)]
+        let (rank, ties, ties_before) = (
+            (start + end + 1) as f64 / 2.,
+            (end - start) as f64,
+            all[start..end].iter().filter(|x| x.1).count() as f64,
+        );
+        rank_sum_before += rank * ties_before;
+        tie_term += ties.powi(3) - ties;
+        start = end;
+    }
+
+    let u = rank_sum_before - n_before * (n_before + 1.) / 2.;
+    let mean = n_before * n_after / 2.;
+    let variance =
+        n_before * n_after / 12. * ((n_total + 1.) - tie_term / (n_total * (n_total - 1.)));
+    if variance <= 0. {
+        // all samples are identical
+        return Some(1.);
+    }
+    // continuity correction
+    let z = ((u - mean).abs() - 0.5).max(0.) / variance.sqrt();
+    Some(erfc(z / std::f64::consts::SQRT_2))
+}
+
+/// Complementary error function for `x >= 0`, see Abramowitz & Stegun 7.1.26
+fn erfc(x: f64) -> f64 {
+    const P: f64 = 0.327_591_1;
+    const A: [f64; 5] = [
+        0.254_829_592,
+        -0.284_496_736,
+        1.421_413_741,
+        -1.453_152_027,
+        1.061_405_429,
+    ];
+    let t = 1. / (1. + P * x);
+    let poly = A.iter().rev().fold(0., |acc, a| (acc + a) * t);
+    poly * (-x * x).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::too_few(&[1.], &[2., 3.], None)]
+    #[case::identical(&[1., 1., 1.], &[1., 1., 1.], Some(1.))]
+    fn test_mann_whitney_u_edge_cases(
+        #[case] before: &[f64],
+        #[case] after: &[f64],
+        #[case] expected: Option<f64>,
+    ) {
+        assert_eq!(mann_whitney_u(before, after), expected);
+    }
+
+    #[test]
+    fn test_mann_whitney_u() {
+        let samples = [1.0, 1.1, 0.9, 1.05, 0.95, 1.02, 0.98, 1.01, 0.99, 1.03];
+        let shifted = samples.map(|x| x + 0.5);
+        let overlapping = samples.map(|x| x + 0.01);
+        // scipy.stats.mannwhitneyu(samples, shifted).pvalue ≈ 0.00018
+        assert!(mann_whitney_u(&samples, &shifted).unwrap() < 0.001);
+        assert!(mann_whitney_u(&samples, &overlapping).unwrap() > 0.05);
+    }
+
+    #[test]
+    fn test_erfc() {
+        assert!((erfc(0.) - 1.).abs() < 1e-6);
+        assert!((erfc(1.) - 0.157_299_2).abs() < 1e-6);
+    }
+}
@@ -1,32 +1,26 @@
-use anyhow::{Context, Result};
-use octocrab::models::Installation;
+use anyhow::Result;
 use secrecy::ExposeSecret;
 
-use crate::{
-    cli,
-    constants::{APP_ID, ORG},
-};
+use crate::octocrab_utils::GitHubClient;
+use crate::{cli, constants::APP_ID};
 
 use super::Auth;
 
-pub(super) async fn auth_to_octocrab(auth: Auth) -> Result<octocrab::Octocrab> {
+/// Create a client from the credentials.
+///
+/// An app client can’t do much by itself, it acts as the installation that received an event,
+/// see [`GitHubClient::installation`].
+pub(super) fn auth_to_octocrab(auth: Auth) -> Result<GitHubClient> {
     match auth {
         cli::Auth::AppKey(app_key) => {
             let key = jsonwebtoken::EncodingKey::from_rsa_pem(app_key.expose_secret().as_bytes())?;
-            let base = octocrab::Octocrab::builder().app(APP_ID, key).build()?;
-            let Installation { id, html_url, .. } = base
-                .apps()
-                .get_org_installation(ORG)
-                .await
-                .context("failed to get org installation")?;
-            tracing::info!(
-                "Found installation: {}",
-                html_url.unwrap_or_else(|| id.to_string())
-            );
-            Ok(octocrab::Octocrab::installation(&base, id)?)
+            let client = octocrab::Octocrab::builder().app(APP_ID, key).build()?;
+            Ok(GitHubClient::app(client))
         }
-        cli::Auth::GitHubToken(github_token) => Ok(octocrab::Octocrab::builder()
-            .personal_token(github_token)
-            .build()?),
+        cli::Auth::GitHubToken(github_token) => Ok(GitHubClient::plain(
+            octocrab::Octocrab::builder()
+                .personal_token(github_token)
+                .build()?,
+        )),
     }
 }
@@ -1,11 +1,15 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use serde::Deserialize;
 
 use anyhow::Result;
 use secrecy::SecretString;
 use std::fmt::Display;
 
-use crate::{constants::ORG, traits::RunConfig, utils::get_credential};
+use crate::{
+    constants::ORG, event::Filter, octocrab_utils::GitHubClient, repo_cache::PrHead,
+    server::admin::QueueCommand, server::trust::TrustPolicy, traits::RunConfig,
+    utils::get_credential,
+};
 
 use super::octocrab_utils::auth_to_octocrab;
 
@@ -38,12 +42,12 @@ pub(crate) struct AuthInner {
 
 impl AuthInner {
     /// If app key or PAT has been set, use it, otherwise use default octocrab.
-    pub(crate) async fn try_into_octocrab(self) -> Result<octocrab::Octocrab> {
+    pub(crate) fn try_into_github_client(self) -> Result<GitHubClient> {
         let auth: Option<Auth> = self.try_into()?;
         if let Some(auth) = auth {
-            auth_to_octocrab(auth).await
+            auth_to_octocrab(auth)
         } else {
-            Ok(octocrab::Octocrab::default())
+            Ok(GitHubClient::plain(octocrab::Octocrab::default()))
         }
     }
 }
@@ -85,6 +89,11 @@ pub(crate) enum Commands {
     Serve(ServeArgs),
     /// Run a single benchmark
     Run(RunBenchmark),
+    /// Compare stored results of two commits, without running benchmarks
+    Compare(CompareArgs),
+    /// Inspect and manage the job queue of a server running on this machine
+    #[command(subcommand)]
+    Queue(QueueCommand),
 }
 
 #[derive(Args)]
@@ -95,22 +104,65 @@ pub(crate) struct ServeArgs {
     /// Webhook secret as configured on GitHub
     #[arg(long, env)]
     pub(crate) secret_token: Option<SecretString>,
+    /// URL the server is reachable at, used to link to published results from PR comments
+    #[arg(long, env)]
+    pub(crate) public_url: Option<String>,
+    #[command(flatten)]
+    pub(crate) trust: TrustPolicy,
+}
+
+#[derive(Args)]
+pub(crate) struct CompareArgs {
+    /// User or organization owning the repository
+    #[arg(long, default_value = ORG)]
+    pub(crate) owner: String,
+    /// Repository that was benchmarked before
+    pub(crate) repo: String,
+    /// Commit, branch, or tag to compare against
+    pub(crate) before: String,
+    /// Commit, branch, or tag to compare
+    pub(crate) after: String,
+    /// Only compare environments whose name contains one of these (default: all)
+    #[arg(long = "env", short = 'E')]
+    pub(crate) envs: Vec<String>,
+    /// Only list benchmarks that changed significantly
+    #[arg(long)]
+    pub(crate) only_changed: bool,
+    #[arg(long, value_enum, default_value_t)]
+    pub(crate) format: Format,
+}
+
+/// Output format of a comparison
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Format {
+    #[default]
+    Markdown,
+    Json,
+    /// Aligned columns like `asv compare`
+    Text,
 }
 
 #[derive(Args, Debug, Clone, Deserialize, PartialEq, Eq)]
 pub(crate) struct RunBenchmark {
-    /// Repository containing ASV benchmarks (in scverse org)
+    /// User or organization owning the repository
+    #[arg(long, default_value = ORG)]
+    #[serde(default = "default_owner")]
+    pub owner: String,
+    /// Repository containing ASV benchmarks
     pub repo: String,
     /// Branch or commit to use benchmark configuration from
     #[arg(long, short)]
     pub config_ref: Option<String>,
     /// Which refs in the target repository to run benchmarks on (default: default branch)
     pub run_on: Vec<String>,
+    #[command(flatten)]
+    #[serde(default)]
+    pub filter: Filter,
 }
 
 impl Display for RunBenchmark {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{ORG}/{}", self.repo)?;
+        write!(f, "{}/{}", self.owner, self.repo)?;
         if let Some(config_ref) = &self.config_ref {
             write!(f, "@{config_ref}")?;
         }
@@ -118,7 +170,14 @@ impl Display for RunBenchmark {
     }
 }
 
+fn default_owner() -> String {
+    ORG.to_owned()
+}
+
 impl RunConfig for RunBenchmark {
+    fn owner(&self) -> &str {
+        &self.owner
+    }
     fn repo(&self) -> &str {
         &self.repo
     }
@@ -128,4 +187,13 @@ impl RunConfig for RunBenchmark {
     fn run_on(&self) -> &[String] {
         self.run_on.as_slice()
     }
+    fn base_ref(&self) -> Option<&str> {
+        None
+    }
+    fn pr_head(&self) -> Option<PrHead> {
+        None
+    }
+    fn filter(&self) -> &Filter {
+        &self.filter
+    }
 }
@@ -4,6 +4,9 @@ pub(crate) const ORG: &str = "scverse";
 pub(crate) const APP_ID: AppId = AppId(858_840);
 pub(crate) const BOT_NAME: &str = "scverse-benchmark[bot]";
 pub(crate) const BENCHMARK_LABEL: &str = "benchmark";
+/// Identifiers of the check run buttons, sent back in `check_run` webhooks
+pub(crate) const RERUN_ACTION: &str = "rerun";
+pub(crate) const CANCEL_ACTION: &str = "cancel";
 pub(crate) const PR_COMPARISON_MARKER: &str =
     "<!-- DO NOT REMOVE: Scverse benchmark run comment marker -->";
 
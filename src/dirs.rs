@@ -0,0 +1,10 @@
+use directories::ProjectDirs;
+use std::{path::Path, sync::LazyLock};
+
+static DIRS: LazyLock<ProjectDirs> = LazyLock::new(|| {
+    ProjectDirs::from("org", "scverse", "scverse-benchmark").expect("No Home dir")
+});
+/// Directory for data that can be recreated, e.g. repository clones.
+pub(crate) static CACHE_DIR: LazyLock<&'static Path> = LazyLock::new(|| DIRS.cache_dir());
+/// Directory for data that has to survive restarts, e.g. the job queue.
+pub(crate) static DATA_DIR: LazyLock<&'static Path> = LazyLock::new(|| DIRS.data_dir());
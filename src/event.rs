@@ -1,21 +1,154 @@
-use octocrab::models::CheckRunId;
+use std::fmt::Display;
 
+use clap::Args;
+use octocrab::models::{CheckRunId, InstallationId};
+use serde::{Deserialize, Serialize};
+
+use crate::constants::ORG;
+use crate::repo_cache::PrHead;
 use crate::traits::RunConfig;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) enum Event {
     Compare(Compare),
+    Run(Run),
+}
+
+impl Event {
+    pub(crate) fn owner(&self) -> &str {
+        match self {
+            Self::Compare(cmp) => &cmp.owner,
+            Self::Run(run) => &run.owner,
+        }
+    }
+    pub(crate) fn repo(&self) -> &str {
+        match self {
+            Self::Compare(cmp) => &cmp.repo,
+            Self::Run(run) => &run.repo,
+        }
+    }
+    pub(crate) fn installation(&self) -> Option<InstallationId> {
+        match self {
+            Self::Compare(cmp) => cmp.installation,
+            Self::Run(run) => run.installation,
+        }
+    }
+    pub(crate) fn check_id(&self) -> Option<CheckRunId> {
+        match self {
+            Self::Compare(cmp) => cmp.check_id,
+            Self::Run(run) => run.check_id,
+        }
+    }
+    /// The PR or commit on GitHub the event is about.
+    pub(crate) fn html_url(&self) -> String {
+        match self {
+            Self::Compare(cmp) => format!(
+                "https://github.com/{}/{}/pull/{}",
+                cmp.owner, cmp.repo, cmp.pr
+            ),
+            Self::Run(run) => format!(
+                "https://github.com/{}/{}/commit/{}",
+                run.owner, run.repo, run.commit
+            ),
+        }
+    }
+    /// The PR an event is about, if any.
+    pub(crate) fn key(&self) -> Option<(&str, &str, u64)> {
+        match self {
+            Self::Compare(cmp) => Some((&cmp.owner, &cmp.repo, cmp.pr)),
+            Self::Run(_) => None,
+        }
+    }
+    /// Whether a job for this event makes a pending job for `other` obsolete.
+    ///
+    /// That’s the case for the same PR if the head changed, or if both run the same benchmarks.
+    /// A filtered run for the same head (e.g. from a `/benchmark` comment) is kept alongside.
+    pub(crate) fn supersedes(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Compare(new), Self::Compare(old)) => {
+                self.key() == other.key()
+                    && (new.commits[1] != old.commits[1] || new.filter == old.filter)
+            }
+            _ => false,
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Compare(cmp) => write!(
+                f,
+                "{}/{}#{} at {}",
+                cmp.owner,
+                cmp.repo,
+                cmp.pr,
+                short(&cmp.commits[1])
+            ),
+            Self::Run(run) => write!(
+                f,
+                "{}/{}@{} at {}",
+                run.owner,
+                run.repo,
+                run.branch,
+                short(&run.commit)
+            ),
+        }
+    }
+}
+
+fn short(commit: &str) -> &str {
+    commit.get(..8).unwrap_or(commit)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) struct Compare {
+    /// User or organization owning the repository
+    #[serde(default = "default_owner")]
+    pub owner: String,
     pub repo: String,
+    /// The app installation that received the event, if any
+    #[serde(default)]
+    pub installation: Option<InstallationId>,
     pub commits: [String; 2],
     pub pr: u64,
+    /// Where the PR’s head branch lives, if not in the repository itself
+    #[serde(default)]
+    pub fork: Option<Fork>,
     pub check_id: Option<CheckRunId>,
+    #[serde(default)]
+    pub filter: Filter,
+}
+
+/// A repository a PR comes from that is not the one it’s merged into.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Fork {
+    /// URL to fetch from, e.g. `https://github.com/someone/repo.git`
+    pub clone_url: String,
+    /// The PR’s head branch in the fork
+    pub branch: String,
+}
+
+/// Restricts which environments and benchmarks are run.
+#[derive(Args, Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Filter {
+    /// Only run in environments whose name contains one of these (default: all)
+    #[arg(long = "env", short = 'E')]
+    pub envs: Vec<String>,
+    /// Regex selecting the benchmarks to run (default: all)
+    #[arg(long, short)]
+    pub bench: Option<String>,
+}
+
+/// Jobs queued before multiple owners were supported are all in our org.
+fn default_owner() -> String {
+    ORG.to_owned()
 }
 
 impl RunConfig for Compare {
+    fn owner(&self) -> &str {
+        &self.owner
+    }
     fn repo(&self) -> &str {
         &self.repo
     }
@@ -25,6 +158,19 @@ impl RunConfig for Compare {
     fn run_on(&self) -> &[String] {
         self.commits.as_slice()
     }
+    fn base_ref(&self) -> Option<&str> {
+        Some(self.commits[0].as_str())
+    }
+    fn pr_head(&self) -> Option<PrHead> {
+        Some(PrHead {
+            number: self.pr,
+            sha: self.commits[1].clone(),
+            fork: self.fork.clone(),
+        })
+    }
+    fn filter(&self) -> &Filter {
+        &self.filter
+    }
 }
 
 impl From<Compare> for Event {
@@ -32,3 +178,50 @@ impl From<Compare> for Event {
         Self::Compare(c)
     }
 }
+
+/// Benchmark a commit pushed to a tracked branch, adding it to the results history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Run {
+    #[serde(default = "default_owner")]
+    pub owner: String,
+    pub repo: String,
+    #[serde(default)]
+    pub installation: Option<InstallationId>,
+    pub branch: String,
+    pub commit: String,
+    pub check_id: Option<CheckRunId>,
+}
+
+impl RunConfig for Run {
+    fn owner(&self) -> &str {
+        &self.owner
+    }
+    fn repo(&self) -> &str {
+        &self.repo
+    }
+    fn config_ref(&self) -> Option<&str> {
+        Some(self.commit.as_str())
+    }
+    fn run_on(&self) -> &[String] {
+        std::slice::from_ref(&self.commit)
+    }
+    fn base_ref(&self) -> Option<&str> {
+        Some(self.commit.as_str())
+    }
+    fn pr_head(&self) -> Option<PrHead> {
+        None
+    }
+    fn filter(&self) -> &Filter {
+        static NO_FILTER: Filter = Filter {
+            envs: vec![],
+            bench: None,
+        };
+        &NO_FILTER
+    }
+}
+
+impl From<Run> for Event {
+    fn from(r: Run) -> Self {
+        Self::Run(r)
+    }
+}
@@ -1,2 +1,53 @@
+use std::fs;
+use std::path::Path;
+use std::slice;
+
+use crate::benchmark::comparison::{Comparison, Thresholds};
+use crate::benchmark::results::{parse_benchmarks, Results};
+
 pub(crate) const PR: &str = include_str!("fixtures/test.hook-pr-sync.json");
 pub(crate) const COMMIT: &str = include_str!("fixtures/test.commit.json");
+pub(crate) const COMMENT: &str = include_str!("fixtures/test.hook-issue-comment.json");
+pub(crate) const PUSH: &str = include_str!("fixtures/test.hook-push.json");
+pub(crate) const ASV_RESULTS_BEFORE: &str = include_str!("fixtures/asv-results-before.json");
+pub(crate) const ASV_RESULTS_AFTER: &str = include_str!("fixtures/asv-results-after.json");
+pub(crate) const ASV_BENCHMARKS: &str = include_str!("fixtures/asv-benchmarks.json");
+
+/// The comparison of [`ASV_RESULTS_BEFORE`] and [`ASV_RESULTS_AFTER`] with the default thresholds.
+pub(crate) fn fixture_comparison() -> Comparison {
+    fixture_comparison_with(&Thresholds::default())
+}
+
+/// The comparison of [`ASV_RESULTS_BEFORE`] and [`ASV_RESULTS_AFTER`].
+pub(crate) fn fixture_comparison_with(thresholds: &Thresholds) -> Comparison {
+    let before = Results::parse(ASV_RESULTS_BEFORE).unwrap();
+    let after = Results::parse(ASV_RESULTS_AFTER).unwrap();
+    Comparison::new(
+        [&before.commit_hash, &after.commit_hash],
+        [slice::from_ref(&before), slice::from_ref(&after)],
+        &parse_benchmarks(ASV_BENCHMARKS).unwrap(),
+        thresholds,
+    )
+}
+
+/// Write `files` to `repo`’s working directory and commit them on top of `parents`, updating `HEAD`.
+pub(crate) fn commit(
+    repo: &git2::Repository,
+    files: &[(&str, &str)],
+    message: &str,
+    parents: &[&git2::Commit],
+) -> git2::Oid {
+    let workdir = repo.workdir().unwrap();
+    let mut index = repo.index().unwrap();
+    for (path, content) in files {
+        let full_path = workdir.join(path);
+        fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+        fs::write(full_path, content).unwrap();
+        index.add_path(Path::new(path)).unwrap();
+    }
+    index.write().unwrap();
+    let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+    let sig = git2::Signature::now("test", "test@example.com").unwrap();
+    repo.commit(Some("HEAD"), &sig, &sig, message, &tree, parents)
+        .unwrap()
+}
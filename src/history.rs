@@ -0,0 +1,497 @@
+//! History of benchmark results across runs, stored in an `SQLite` database.
+//!
+//! asv’s result files stay in the repository cache, this indexes them by
+//! repository, commit, machine, environment, and benchmark/parameters.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+
+use crate::benchmark::results::{load_benchmarks, BenchmarkInfo, BenchmarkResult, Results, Stats};
+use crate::dirs::DATA_DIR;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    owner TEXT NOT NULL,
+    repo TEXT NOT NULL,
+    commit_hash TEXT NOT NULL,
+    commit_date TEXT,
+    machine TEXT NOT NULL,
+    env TEXT NOT NULL,
+    recorded_at TEXT NOT NULL,
+    UNIQUE (owner, repo, commit_hash, machine, env)
+);
+CREATE TABLE IF NOT EXISTS benchmarks (
+    run_id INTEGER NOT NULL REFERENCES runs (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    version TEXT,
+    kind TEXT NOT NULL,
+    unit TEXT NOT NULL,
+    param_names TEXT NOT NULL,
+    params TEXT NOT NULL,
+    PRIMARY KEY (run_id, name)
+);
+CREATE TABLE IF NOT EXISTS measurements (
+    run_id INTEGER NOT NULL REFERENCES runs (id) ON DELETE CASCADE,
+    benchmark TEXT NOT NULL,
+    param_index INTEGER NOT NULL,
+    params TEXT NOT NULL,
+    value REAL,
+    skipped INTEGER NOT NULL,
+    stats TEXT,
+    samples TEXT,
+    PRIMARY KEY (run_id, benchmark, param_index)
+);
+CREATE INDEX IF NOT EXISTS measurements_by_benchmark ON measurements (benchmark);
+";
+
+/// Stored benchmark results.
+#[derive(Debug)]
+pub(crate) struct History {
+    conn: Mutex<Connection>,
+}
+
+/// One measurement of a benchmark over time.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Point {
+    pub commit_hash: String,
+    pub commit_date: Option<DateTime<Utc>>,
+    pub machine: String,
+    pub env: String,
+    pub params: Vec<String>,
+    /// `None` if the benchmark failed
+    pub value: Option<f64>,
+    /// Half width of the confidence interval, if recorded
+    pub err: Option<f64>,
+}
+
+impl History {
+    /// Where the history is stored by default.
+    pub fn default_path() -> PathBuf {
+        DATA_DIR.join("history.sqlite")
+    }
+
+    /// Open the history stored at `path`, or create an empty one.
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("failed to open history at {}", path.display()))?;
+        // the runner and the status server might write at the same time
+        conn.busy_timeout(std::time::Duration::from_secs(10))?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Store the results for a commit from asv’s results directory.
+    ///
+    /// Returns the number of stored result files, i.e. machine/environment combinations.
+    pub fn record(
+        &self,
+        owner: &str,
+        repo: &str,
+        results_dir: &Path,
+        commit: &str,
+    ) -> Result<usize> {
+        let benchmarks = load_benchmarks(results_dir)?;
+        let all = Results::load_all(results_dir, commit)?;
+        for results in &all {
+            self.insert(owner, repo, results, &benchmarks)?;
+        }
+        Ok(all.len())
+    }
+
+    /// Store results, replacing earlier ones for the same commit, machine, and environment.
+    pub fn insert(
+        &self,
+        owner: &str,
+        repo: &str,
+        results: &Results,
+        benchmarks: &BTreeMap<String, BenchmarkInfo>,
+    ) -> Result<()> {
+        let mut conn = self.conn.lock().expect("history lock poisoned");
+        let tx = conn.transaction()?;
+        let run_id: i64 = tx.query_row(
+            "INSERT INTO runs (owner, repo, commit_hash, commit_date, machine, env, recorded_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            ON CONFLICT (owner, repo, commit_hash, machine, env)
+            DO UPDATE SET commit_date = excluded.commit_date, recorded_at = excluded.recorded_at
+            RETURNING id",
+            params![
+                owner,
+                repo,
+                results.commit_hash,
+                results.date,
+                results.machine,
+                results.env_name,
+                Utc::now(),
+            ],
+            |row| row.get(0),
+        )?;
+        tx.execute("DELETE FROM benchmarks WHERE run_id = ?1", [run_id])?;
+        tx.execute("DELETE FROM measurements WHERE run_id = ?1", [run_id])?;
+        for (name, result) in &results.benchmarks {
+            let info = benchmarks.get(name);
+            tx.execute(
+                "INSERT INTO benchmarks (run_id, name, version, kind, unit, param_names, params)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    run_id,
+                    name,
+                    result.version,
+                    info.map_or("", |info| &info.kind),
+                    info.map_or("", |info| &info.unit),
+                    serde_json::to_string(&info.map(|info| &info.param_names))?,
+                    serde_json::to_string(&result.params)?,
+                ],
+            )?;
+            for (i, params) in result.param_combinations().iter().enumerate() {
+                let value = result.values.get(i).copied().flatten();
+                let stats = result.stats.get(i).copied().flatten();
+                let samples = result.samples.get(i).cloned().flatten();
+                tx.execute(
+                    "INSERT INTO measurements
+                    (run_id, benchmark, param_index, params, value, skipped, stats, samples)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    params![
+                        run_id,
+                        name,
+                        i64::try_from(i)?,
+                        serde_json::to_string(params)?,
+                        // SQLite would store NaN as NULL, i.e. failed
+                        value.filter(|v| !v.is_nan()),
+                        value.is_some_and(f64::is_nan),
+                        stats.map(|s| serde_json::to_string(&s)).transpose()?,
+                        samples.map(|s| serde_json::to_string(&s)).transpose()?,
+                    ],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Load all results for a commit (or unique commit prefix), and metadata about their benchmarks.
+    ///
+    /// Fails with a [`CommitLookupError`] if the prefix is empty or matches several commits.
+    pub fn load(
+        &self,
+        owner: &str,
+        repo: &str,
+        commit: &str,
+    ) -> Result<(Vec<Results>, BTreeMap<String, BenchmarkInfo>)> {
+        if commit.is_empty() {
+            bail!(CommitLookupError::Empty);
+        }
+        let conn = self.conn.lock().expect("history lock poisoned");
+        let mut runs = conn.prepare(
+            "SELECT id, commit_hash, commit_date, machine, env FROM runs
+            WHERE owner = ?1 AND repo = ?2 AND substr(commit_hash, 1, length(?3)) = ?3
+            ORDER BY machine, env",
+        )?;
+        let runs = runs
+            .query_map(params![owner, repo, commit], |row| {
+                let id: i64 = row.get(0)?;
+                let results = Results {
+                    commit_hash: row.get(1)?,
+                    date: row.get(2)?,
+                    machine: row.get(3)?,
+                    env_name: row.get(4)?,
+                    benchmarks: BTreeMap::new(),
+                };
+                Ok((id, results))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let commits: BTreeSet<&str> = runs.iter().map(|(_, r)| r.commit_hash.as_str()).collect();
+        if commits.len() > 1 {
+            bail!(CommitLookupError::Ambiguous {
+                prefix: commit.to_owned(),
+                commits: commits.into_iter().map(str::to_owned).collect(),
+            });
+        }
+        let mut infos = BTreeMap::new();
+        let mut all = vec![];
+        for (run_id, mut results) in runs {
+            let mut benchmarks = conn.prepare(
+                "SELECT name, version, kind, unit, param_names, params FROM benchmarks
+                WHERE run_id = ?1",
+            )?;
+            let mut rows = benchmarks.query([run_id])?;
+            while let Some(row) = rows.next()? {
+                let name: String = row.get(0)?;
+                let param_names: Option<Vec<String>> =
+                    serde_json::from_str(&row.get::<_, String>(4)?)?;
+                infos.insert(
+                    name.clone(),
+                    BenchmarkInfo {
+                        name: name.clone(),
+                        code: String::new(),
+                        kind: row.get(2)?,
+                        unit: row.get(3)?,
+                        param_names: param_names.unwrap_or_default(),
+                    },
+                );
+                let result = BenchmarkResult {
+                    params: serde_json::from_str(&row.get::<_, String>(5)?)?,
+                    version: row.get(1)?,
+                    ..BenchmarkResult::default()
+                };
+                results.benchmarks.insert(name, result);
+            }
+            let mut measurements = conn.prepare(
+                "SELECT benchmark, value, skipped, stats, samples FROM measurements
+                WHERE run_id = ?1 ORDER BY benchmark, param_index",
+            )?;
+            let mut rows = measurements.query([run_id])?;
+            while let Some(row) = rows.next()? {
+                let name: String = row.get(0)?;
+                let Some(result) = results.benchmarks.get_mut(&name) else {
+                    continue;
+                };
+                let skipped: bool = row.get(2)?;
+                let value: Option<f64> = row.get(1)?;
+                result
+                    .values
+                    .push(if skipped { Some(f64::NAN) } else { value });
+                let stats: Option<String> = row.get(3)?;
+                result.stats.push(
+                    stats
+                        .map(|s| serde_json::from_str::<Stats>(&s))
+                        .transpose()?,
+                );
+                let samples: Option<String> = row.get(4)?;
+                result
+                    .samples
+                    .push(samples.map(|s| serde_json::from_str(&s)).transpose()?);
+            }
+            // like when parsing, results without any samples have none
+            for result in results.benchmarks.values_mut() {
+                if result.samples.iter().all(Option::is_none) {
+                    result.samples.clear();
+                }
+            }
+            all.push(results);
+        }
+        Ok((all, infos))
+    }
+
+    /// Measurements of a benchmark over time, ordered by commit date. Skipped ones are left out.
+    pub fn time_series(&self, owner: &str, repo: &str, benchmark: &str) -> Result<Vec<Point>> {
+        let conn = self.conn.lock().expect("history lock poisoned");
+        let mut stmt = conn.prepare(
+            "SELECT runs.commit_hash, runs.commit_date, runs.machine, runs.env,
+                measurements.params, measurements.value, measurements.stats
+            FROM measurements JOIN runs ON runs.id = measurements.run_id
+            WHERE runs.owner = ?1 AND runs.repo = ?2 AND measurements.benchmark = ?3
+                AND NOT measurements.skipped
+            ORDER BY runs.commit_date, runs.recorded_at, measurements.param_index",
+        )?;
+        let mut rows = stmt.query(params![owner, repo, benchmark])?;
+        let mut points = vec![];
+        while let Some(row) = rows.next()? {
+            let stats: Option<String> = row.get(6)?;
+            let stats: Option<Stats> = stats.map(|s| serde_json::from_str(&s)).transpose()?;
+            points.push(Point {
+                commit_hash: row.get(0)?,
+                commit_date: row.get(1)?,
+                machine: row.get(2)?,
+                env: row.get(3)?,
+                params: serde_json::from_str(&row.get::<_, String>(4)?)?,
+                value: row.get(5)?,
+                err: stats.map(|s| s.err()),
+            });
+        }
+        Ok(points)
+    }
+}
+
+/// A commit prefix that doesn’t identify a single commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum CommitLookupError {
+    /// The prefix is empty, so it would match every commit.
+    Empty,
+    /// Results of several commits match the prefix.
+    Ambiguous {
+        prefix: String,
+        commits: Vec<String>,
+    },
+}
+
+impl Display for CommitLookupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty commit prefix"),
+            Self::Ambiguous { prefix, commits } => write!(
+                f,
+                "commit prefix {prefix} is ambiguous, it matches {}",
+                commits.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CommitLookupError {}
+
+/// No results were recorded for a commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct NoResults {
+    pub owner: String,
+    pub repo: String,
+    pub commit: String,
+}
+
+impl Display for NoResults {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "no results for {}/{}@{} in history",
+            self.owner, self.repo, self.commit
+        )
+    }
+}
+
+impl std::error::Error for NoResults {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixtures::{ASV_BENCHMARKS, ASV_RESULTS_AFTER, ASV_RESULTS_BEFORE};
+
+    fn history(tmp_dir: &Path) -> History {
+        let history = History::open(&tmp_dir.join("history.sqlite")).unwrap();
+        let benchmarks = crate::benchmark::results::parse_benchmarks(ASV_BENCHMARKS).unwrap();
+        for json in [ASV_RESULTS_BEFORE, ASV_RESULTS_AFTER] {
+            let results = Results::parse(json).unwrap();
+            history
+                .insert("scverse", "benchmark", &results, &benchmarks)
+                .unwrap();
+        }
+        history
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let history = tmp_dir.used_by(history);
+        let expected = Results::parse(ASV_RESULTS_AFTER).unwrap();
+        let (loaded, infos) = history
+            .load("scverse", "benchmark", &expected.commit_hash[..8])
+            .unwrap();
+        let [loaded] = loaded.as_slice() else {
+            panic!("expected one result, got {loaded:?}");
+        };
+        assert_eq!(loaded.commit_hash, expected.commit_hash);
+        assert_eq!(loaded.date, expected.date);
+        // NaN != NaN, so compare skipped values separately
+        for (name, result) in &expected.benchmarks {
+            let loaded = &loaded.benchmarks[name];
+            assert_eq!(loaded.params, result.params);
+            assert_eq!(loaded.version, result.version);
+            assert_eq!(loaded.stats, result.stats);
+            assert_eq!(loaded.samples, result.samples);
+            let values = |r: &BenchmarkResult| -> Vec<_> {
+                r.values
+                    .iter()
+                    .map(|v| {
+                        v.map(|v| {
+                            if v.is_nan() {
+                                "skipped".to_owned()
+                            } else {
+                                v.to_string()
+                            }
+                        })
+                    })
+                    .collect()
+            };
+            assert_eq!(values(loaded), values(result), "{name}");
+        }
+        assert_eq!(infos["benchmarks.MemSuite.mem_list"].unit, "bytes");
+        // other repositories are separate
+        let (other, _) = history
+            .load("scverse", "other", &expected.commit_hash)
+            .unwrap();
+        assert!(other.is_empty());
+    }
+
+    #[test]
+    fn test_load_ambiguous() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let history = tmp_dir.used_by(history);
+        let mut results = Results::parse(ASV_RESULTS_BEFORE).unwrap();
+        let first = results.commit_hash.clone();
+        results.commit_hash = format!("{}ffff", &first[..8]);
+        history
+            .insert("scverse", "benchmark", &results, &BTreeMap::new())
+            .unwrap();
+        let error = history
+            .load("scverse", "benchmark", &first[..8])
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CommitLookupError>(),
+            Some(&CommitLookupError::Ambiguous {
+                prefix: first[..8].to_owned(),
+                commits: vec![first.clone(), results.commit_hash],
+            })
+        );
+        // a longer prefix is unique again
+        let (loaded, _) = history.load("scverse", "benchmark", &first).unwrap();
+        assert_eq!(loaded.len(), 1);
+    }
+
+    #[test]
+    fn test_load_empty_prefix() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let history = tmp_dir.used_by(history);
+        let error = history.load("scverse", "benchmark", "").unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CommitLookupError>(),
+            Some(&CommitLookupError::Empty)
+        );
+    }
+
+    #[test]
+    fn test_replace() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let history = tmp_dir.used_by(history);
+        let results = Results::parse(ASV_RESULTS_AFTER).unwrap();
+        history
+            .insert("scverse", "benchmark", &results, &BTreeMap::new())
+            .unwrap();
+        let (loaded, _) = history
+            .load("scverse", "benchmark", &results.commit_hash)
+            .unwrap();
+        assert_eq!(loaded.len(), 1);
+    }
+
+    #[test]
+    fn test_time_series() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let history = tmp_dir.used_by(history);
+        let points = history
+            .time_series("scverse", "benchmark", "benchmarks.TimeSuite.time_iterkeys")
+            .unwrap();
+        let values: Vec<_> = points.iter().map(|p| p.value).collect();
+        assert_eq!(values, vec![Some(1e-6), Some(2.5e-6)]);
+        assert!(points[0].commit_date <= points[1].commit_date);
+
+        // skipped measurements are left out, failed ones kept
+        let points = history
+            .time_series("scverse", "benchmark", "benchmarks.ParamSuite.time_sum")
+            .unwrap();
+        let after = Results::parse(ASV_RESULTS_AFTER).unwrap().commit_hash;
+        let after: Vec<_> = points.iter().filter(|p| p.commit_hash == after).collect();
+        assert_eq!(after.len(), 3);
+        assert!(after.iter().any(|p| p.value.is_none()));
+    }
+}
@@ -1,18 +1,24 @@
 #![warn(clippy::pedantic)]
 
 use anyhow::{bail, Result};
-use benchmark::RunResult;
+use std::sync::Arc;
+
+use benchmark::{comparison::Comparison, RunResult};
 use clap::Parser;
+use history::History;
 
 mod benchmark;
 mod cli;
 mod constants;
+mod dirs;
 mod event;
 #[cfg(test)]
 mod fixtures;
+mod history;
 mod nightly_backports;
 mod octocrab_utils;
 mod repo_cache;
+mod repo_config;
 mod server;
 mod traits;
 mod utils;
@@ -21,32 +27,63 @@ mod utils;
 async fn main() -> Result<()> {
     cli::init_tracing();
 
-    let mut cli = cli::Cli::parse();
-
-    // Set global octocrab instance, either using the provided auth or in --dry-run mode
-    octocrab::initialise(std::mem::take(&mut cli.auth).try_into_octocrab().await?);
+    let cli = cli::Cli::parse();
 
     match cli.command {
         cli::Commands::Serve(args) => {
-            server::serve(args).await?;
+            // either using the provided auth or in --dry-run mode
+            let github = cli.auth.try_into_github_client()?;
+            server::serve(args, github).await?;
         }
         cli::Commands::Run(args) => {
+            let history = Arc::new(History::open(&History::default_path())?);
             let RunResult {
                 success,
                 wd,
+                results_dir,
+                thresholds,
                 env_specs,
-            } = benchmark::sync_repo_and_run(&args).await?;
+                ..
+            } = benchmark::sync_repo_and_run(&args, &history, None).await?;
             // if exactly two are specified, show a comparison
             if let [before, after] = args.run_on.as_slice() {
-                benchmark::AsvCompare::new(&wd, before, after)
-                    .in_envs(env_specs)
-                    .run()
-                    .await?;
+                let before = benchmark::resolve_commit(&wd, before)?;
+                let after = benchmark::resolve_commit(&wd, after)?;
+                let comparison =
+                    Comparison::load(&results_dir, &before, &after, &env_specs.0, &thresholds)?;
+                println!("{}", comparison.markdown(false));
             }
             if !success {
                 bail!("Benchmark run failed");
             }
         }
+        cli::Commands::Compare(args) => {
+            let mut comparison = benchmark::compare_cached(
+                &args.owner,
+                &args.repo,
+                [&args.before, &args.after],
+                &args.envs,
+            )?;
+            let output = match args.format {
+                cli::Format::Markdown => comparison.markdown(args.only_changed),
+                cli::Format::Text => comparison.text(args.only_changed),
+                cli::Format::Json => {
+                    if args.only_changed {
+                        comparison.rows.retain(|row| row.change.is_change());
+                    }
+                    serde_json::to_string_pretty(&comparison)?
+                }
+            };
+            println!("{}", output.trim_end());
+        }
+        cli::Commands::Queue(command) => {
+            match server::admin::request(&server::admin::socket_path(), &command).await? {
+                server::admin::Reply::Status(status) => {
+                    print!("{}", server::admin::format_status(&status));
+                }
+                server::admin::Reply::Done { message } => println!("{message}"),
+            }
+        }
     }
     Ok(())
 }
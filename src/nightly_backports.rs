@@ -19,6 +19,22 @@ pub(crate) fn floor_char_boundary(s: &str, index: usize) -> usize {
     }
 }
 
+/// Finds the closest `x` not below `index` where `is_char_boundary(x)` is `true`.
+///
+/// See <https://doc.rust-lang.org/std/primitive.str.html#method.ceil_char_boundary>
+#[inline]
+pub(crate) fn ceil_char_boundary(s: &str, index: usize) -> usize {
+    if index > s.len() {
+        s.len()
+    } else {
+        let upper_bound = Ord::min(index + 4, s.len());
+        s.as_bytes()[index..upper_bound]
+            .iter()
+            .position(|b| is_utf8_char_boundary(*b))
+            .map_or(upper_bound, |pos| pos + index)
+    }
+}
+
 #[allow(clippy::cast_possible_wrap)]
 #[inline]
 pub(crate) fn is_utf8_char_boundary(b: u8) -> bool {
@@ -1,33 +1,144 @@
+use std::collections::HashMap;
 use std::pin::pin;
+use std::sync::{Arc, Mutex};
 
+use anyhow::{Context, Result};
 use futures::{future, TryStreamExt};
-use octocrab::Page;
+use octocrab::{models::InstallationId, Octocrab, Page};
 use serde::de::DeserializeOwned;
 
 pub(super) trait PageExt<I>
 where
     I: DeserializeOwned + 'static,
 {
-    async fn find<F: Fn(&I) -> bool>(
+    async fn filter<F: Fn(&I) -> bool>(
         self,
         github_api: &octocrab::Octocrab,
         pred: F,
-    ) -> octocrab::Result<Option<I>>;
+    ) -> octocrab::Result<Vec<I>>;
 }
 
 impl<I> PageExt<I> for Page<I>
 where
     I: DeserializeOwned + 'static,
 {
-    async fn find<F: Fn(&I) -> bool>(
+    async fn filter<F: Fn(&I) -> bool>(
         self,
         github_api: &octocrab::Octocrab,
         pred: F,
-    ) -> octocrab::Result<Option<I>> {
+    ) -> octocrab::Result<Vec<I>> {
         let items = pin!(self.into_stream(github_api));
         items
             .try_filter(|item| future::ready(pred(item)))
-            .try_next()
+            .try_collect()
             .await
     }
 }
+
+/// A GitHub client, and how it is authenticated.
+#[derive(Debug)]
+pub(crate) struct GitHubClient {
+    client: Arc<Octocrab>,
+    /// Clients acting as the app’s installations, if `client` is authenticated as an app.
+    /// Kept so their access tokens are reused.
+    installations: Option<Mutex<HashMap<InstallationId, Arc<Octocrab>>>>,
+}
+
+impl GitHubClient {
+    /// A client authenticated as an app, which acts as the installation for each repository.
+    pub(crate) fn app(client: Octocrab) -> Self {
+        Self {
+            client: Arc::new(client),
+            installations: Some(Mutex::default()),
+        }
+    }
+
+    /// A client that acts as itself for all repositories, e.g. authenticated with a token.
+    pub(crate) fn plain(client: Octocrab) -> Self {
+        Self {
+            client: Arc::new(client),
+            installations: None,
+        }
+    }
+
+    /// The client as authenticated, e.g. as the app itself.
+    pub(crate) fn client(&self) -> &Arc<Octocrab> {
+        &self.client
+    }
+
+    /// Get a client acting as the app installation for `owner/repo`.
+    ///
+    /// If `installation` is `None`, it is looked up via the GitHub API.
+    /// If the client isn’t authenticated as an app, it is returned as is.
+    pub(crate) async fn installation(
+        &self,
+        owner: &str,
+        repo: &str,
+        installation: Option<InstallationId>,
+    ) -> Result<Arc<Octocrab>> {
+        let Some(installations) = &self.installations else {
+            return Ok(self.client.clone());
+        };
+        let id = if let Some(id) = installation {
+            id
+        } else {
+            self.client
+                .apps()
+                .get_repository_installation(owner, repo)
+                .await
+                .with_context(|| format!("failed to get installation for {owner}/{repo}"))?
+                .id
+        };
+        let mut clients = installations
+            .lock()
+            .expect("installation clients lock poisoned");
+        if let Some(client) = clients.get(&id) {
+            return Ok(client.clone());
+        }
+        let client = Arc::new(self.client.installation(id)?);
+        clients.insert(id, client.clone());
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_installation_plain() {
+        let github = GitHubClient::plain(Octocrab::default());
+        let client = github
+            .installation("o", "r", Some(InstallationId(1)))
+            .await
+            .unwrap();
+        assert!(Arc::ptr_eq(&client, github.client()));
+    }
+
+    #[tokio::test]
+    async fn test_installation_app() {
+        let key = jsonwebtoken::EncodingKey::from_secret(b"secret");
+        let github = GitHubClient::app(
+            Octocrab::builder()
+                .app(crate::constants::APP_ID, key)
+                .build()
+                .unwrap(),
+        );
+        let client = github
+            .installation("o", "r", Some(InstallationId(1)))
+            .await
+            .unwrap();
+        assert!(!Arc::ptr_eq(&client, github.client()));
+        // reused for the same installation
+        let again = github
+            .installation("o", "r2", Some(InstallationId(1)))
+            .await
+            .unwrap();
+        assert!(Arc::ptr_eq(&client, &again));
+        let other = github
+            .installation("o2", "r", Some(InstallationId(2)))
+            .await
+            .unwrap();
+        assert!(!Arc::ptr_eq(&client, &other));
+    }
+}
@@ -1,39 +1,119 @@
+use std::fmt::Display;
+
 use anyhow::{anyhow, Context, Result};
-use directories::ProjectDirs;
-use std::{path::Path, sync::LazyLock};
 
-use crate::constants::ORG;
+use crate::dirs::CACHE_DIR;
+use crate::event::Fork;
+
+/// A PR’s head commit, which might only be reachable from a fork or the PR’s ref.
+#[derive(Debug, Clone)]
+pub(crate) struct PrHead {
+    pub number: u64,
+    pub sha: String,
+    pub fork: Option<Fork>,
+}
 
-static DIRS: LazyLock<ProjectDirs> = LazyLock::new(|| {
-    ProjectDirs::from("org", "scverse", "scverse-benchmark").expect("No Home dir")
-});
-static CACHE_DIR: LazyLock<&'static Path> = LazyLock::new(|| DIRS.cache_dir());
+/// A commit that can’t be fetched (anymore), e.g. because a PR was force-pushed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MissingCommit(pub String);
+
+impl Display for MissingCommit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Commit {} doesn’t exist anymore, the PR was probably force-pushed",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for MissingCommit {}
 
 /// Sync repo to match remote’s ref. If ref is None, sync to default branch.
-pub(crate) fn sync_repo(repo: &str, to_ref: Option<&str>) -> Result<(git2::Repository, String)> {
-    let path = CACHE_DIR.join(repo);
+///
+/// If `pr_head` is given, it is fetched first, so `to_ref` can be the PR’s head commit.
+pub(crate) fn sync_repo(
+    owner: &str,
+    repo: &str,
+    to_ref: Option<&str>,
+    pr_head: Option<&PrHead>,
+) -> Result<(git2::Repository, String)> {
+    let path = CACHE_DIR.join(owner).join(repo);
     let repo = if path.is_dir() {
         git2::Repository::open(path)?
     } else {
-        let url = format!("https://github.com/{ORG}/{repo}.git");
+        let url = format!("https://github.com/{owner}/{repo}.git");
         git2::build::RepoBuilder::new()
             .clone(&url, &path)
             .context(anyhow!("failed to clone {url}"))?
     };
-    // fetch from remote
+    if let Some(pr_head) = pr_head {
+        fetch_pr_head(&repo, pr_head)?;
+    }
+    // fetch from remote, unless it’s a commit we already have
     let to_ref = {
         let mut remote = repo.find_remote("origin")?;
         remote.connect(git2::Direction::Fetch)?;
         let to_ref = to_ref.map_or_else(|| get_default_branch(&remote), |b| Ok(b.to_owned()))?;
-        remote.fetch(&[&to_ref], None, None)?;
+        if let Some(oid) = local_commit(&repo, &to_ref) {
+            repo.set_head_detached(oid)?;
+        } else {
+            remote.fetch(&[&to_ref], None, None)?;
+            // switch to first ref in FETCH_HEAD
+            repo.set_head("FETCH_HEAD")?;
+        }
         to_ref
     };
-    // switch to first ref in FETCH_HEAD
-    repo.set_head("FETCH_HEAD")?;
     repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))?;
     Ok((repo, to_ref))
 }
 
+/// Fetch a PR’s head commit from the fork it comes from, or else from `refs/pull/<n>/head`.
+///
+/// Both are stored in the local `refs/pull/<n>/head`.
+/// Fails with [`MissingCommit`] if neither contains the commit,
+/// i.e. the PR was force-pushed since the job was queued.
+pub(crate) fn fetch_pr_head(repo: &git2::Repository, pr_head: &PrHead) -> Result<()> {
+    let PrHead { number, sha, fork } = pr_head;
+    if local_commit(repo, sha).is_some() {
+        return Ok(());
+    }
+    let local_ref = format!("refs/pull/{number}/head");
+    if let Some(Fork { clone_url, branch }) = fork {
+        tracing::info!("Fetching {branch} from fork {clone_url}");
+        let refspec = format!("+refs/heads/{branch}:{local_ref}");
+        if let Err(e) = repo
+            .remote_anonymous(clone_url)
+            .and_then(|mut remote| remote.fetch(&[refspec], None, None))
+        {
+            tracing::warn!("Failed to fetch from fork {clone_url}: {e}");
+        }
+    }
+    if local_commit(repo, sha).is_none() {
+        tracing::info!("Fetching {local_ref} from origin");
+        let refspec = format!("+{local_ref}:{local_ref}");
+        if let Err(e) = repo
+            .find_remote("origin")
+            .and_then(|mut remote| remote.fetch(&[refspec], None, None))
+        {
+            tracing::warn!("Failed to fetch {local_ref}: {e}");
+        }
+    }
+    if local_commit(repo, sha).is_none() {
+        return Err(MissingCommit(sha.clone()).into());
+    }
+    Ok(())
+}
+
+/// Get the commit for a full commit hash, if it’s in the local repository.
+pub(crate) fn local_commit(repo: &git2::Repository, rev: &str) -> Option<git2::Oid> {
+    if rev.len() != 40 {
+        return None;
+    }
+    let oid = git2::Oid::from_str(rev).ok()?;
+    repo.find_commit(oid).ok().map(|commit| commit.id())
+}
+
 fn get_default_branch(remote: &git2::Remote) -> Result<String> {
     Ok(remote
         .default_branch()?
@@ -41,3 +121,85 @@ fn get_default_branch(remote: &git2::Remote) -> Result<String> {
         .context("default branch is not valid UTF-8")?
         .to_owned())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    use crate::fixtures::commit;
+
+    /// Create an upstream repository, a fork with a `feature` branch, and a clone of upstream.
+    fn setup(dir: &Path) -> (git2::Repository, git2::Oid) {
+        let upstream = git2::Repository::init(dir.join("upstream")).unwrap();
+        commit(&upstream, &[], "initial", &[]);
+        let fork =
+            git2::Repository::clone(dir.join("upstream").to_str().unwrap(), dir.join("fork"))
+                .unwrap();
+        let parent = fork.head().unwrap().peel_to_commit().unwrap();
+        let head = commit(&fork, &[], "feature", &[&parent]);
+        fork.branch("feature", &fork.find_commit(head).unwrap(), false)
+            .unwrap();
+        let clone =
+            git2::Repository::clone(dir.join("upstream").to_str().unwrap(), dir.join("clone"))
+                .unwrap();
+        (clone, head)
+    }
+
+    #[test]
+    fn test_fetch_pr_head_from_fork() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        tmp_dir.used_by(|dir| {
+            let (clone, head) = setup(dir);
+            assert_eq!(local_commit(&clone, &head.to_string()), None);
+            let pr_head = PrHead {
+                number: 1,
+                sha: head.to_string(),
+                fork: Some(Fork {
+                    clone_url: dir.join("fork").to_str().unwrap().to_owned(),
+                    branch: "feature".to_owned(),
+                }),
+            };
+            fetch_pr_head(&clone, &pr_head).unwrap();
+            assert_eq!(local_commit(&clone, &head.to_string()), Some(head));
+            let local_ref = clone.refname_to_id("refs/pull/1/head").unwrap();
+            assert_eq!(local_ref, head);
+        });
+    }
+
+    #[test]
+    fn test_fetch_pr_head_force_pushed() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        tmp_dir.used_by(|dir| {
+            let (clone, _head) = setup(dir);
+            let gone = "0d41f8596349daeadaa17c551fa0598f0a95666d";
+            let pr_head = PrHead {
+                number: 1,
+                sha: gone.to_owned(),
+                fork: Some(Fork {
+                    clone_url: dir.join("fork").to_str().unwrap().to_owned(),
+                    branch: "feature".to_owned(),
+                }),
+            };
+            let err = fetch_pr_head(&clone, &pr_head).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<MissingCommit>(),
+                Some(&MissingCommit(gone.to_owned()))
+            );
+        });
+    }
+
+    #[test]
+    fn test_local_commit() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        tmp_dir.used_by(|dir| {
+            let repo = git2::Repository::init(dir).unwrap();
+            let oid = commit(&repo, &[], "initial", &[]);
+            assert_eq!(local_commit(&repo, &oid.to_string()), Some(oid));
+            // only full hashes are considered, so branch names get fetched
+            assert_eq!(local_commit(&repo, &oid.to_string()[..8]), None);
+            assert_eq!(local_commit(&repo, "main"), None);
+        });
+    }
+}
@@ -0,0 +1,323 @@
+//! Per-repository configuration
+use std::collections::BTreeMap;
+use std::path::Component;
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::benchmark::comparison::Thresholds;
+use crate::benchmark::process::Phase;
+use crate::benchmark::{AsvConfig, ASV_CONFIG_PATHS};
+use crate::constants::BENCHMARK_LABEL;
+
+/// Where the configuration can be found, relative to the repository root
+pub(crate) const REPO_CONFIG_PATH: &str = ".github/benchmark.toml";
+
+/// Configuration read from [`REPO_CONFIG_PATH`] on the base branch. All fields are optional.
+///
+/// ```toml
+/// label = "benchmark"
+/// benchmark_dir = "benchmarks"
+/// envs = ["py3.12"]
+/// bench = "time_.*"
+/// comments = "history"
+///
+/// [timeouts]
+/// benchmark = 300
+/// discover = 600
+/// run = 21600
+/// publish = 600
+///
+/// [limits]
+/// memory = 16384
+/// cpu = 3600
+///
+/// [thresholds]
+/// default = 0.1
+/// alpha = 0.01
+/// benchmarks = { "preprocessing\\..*" = 0.2 }
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct RepoConfig {
+    /// PR label that triggers benchmark runs
+    pub label: String,
+    /// Directory containing `asv.conf.json` (default: `benchmarks` or the repository root)
+    pub benchmark_dir: Option<String>,
+    /// Only run in environments whose name contains one of these, unless a run specifies its own
+    pub envs: Vec<String>,
+    /// Regex selecting the benchmarks to run, unless a run specifies its own
+    pub bench: Option<String>,
+    pub comments: Comments,
+    pub timeouts: Timeouts,
+    pub limits: Limits,
+    pub thresholds: ThresholdsConfig,
+}
+
+impl Default for RepoConfig {
+    fn default() -> Self {
+        Self {
+            label: BENCHMARK_LABEL.to_owned(),
+            benchmark_dir: None,
+            envs: vec![],
+            bench: None,
+            comments: Comments::default(),
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            thresholds: ThresholdsConfig::default(),
+        }
+    }
+}
+
+/// How PR comments keep the results of earlier runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Comments {
+    /// Update a single comment, listing earlier runs in a collapsed section
+    #[default]
+    History,
+    /// Post a new comment per run, hiding earlier ones as outdated
+    New,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Timeouts {
+    /// Seconds each benchmark may take, overriding the benchmarks’ `timeout` attribute
+    pub benchmark: Option<u32>,
+    /// Seconds discovering benchmarks may take (default: 10 minutes)
+    pub discover: Option<u32>,
+    /// Seconds running all benchmarks may take (default: 6 hours)
+    pub run: Option<u32>,
+    /// Seconds publishing the results as HTML site may take (default: 10 minutes)
+    pub publish: Option<u32>,
+}
+
+impl Timeouts {
+    /// The wall-clock timeout for an `asv` process in `phase`.
+    pub fn for_phase(&self, phase: Phase) -> Duration {
+        let (timeout, default) = match phase {
+            Phase::Discover => (self.discover, 10 * 60),
+            Phase::Run => (self.run, 6 * 60 * 60),
+            Phase::Publish => (self.publish, 10 * 60),
+        };
+        Duration::from_secs(timeout.unwrap_or(default).into())
+    }
+}
+
+/// Resource limits applied to each `asv` process and its subprocesses.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Limits {
+    /// MiB of (virtual) memory each process may use
+    pub memory: Option<u64>,
+    /// Seconds of CPU time each process may use
+    pub cpu: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ThresholdsConfig {
+    /// Relative change above which a significant change is reported as such
+    pub default: Option<f64>,
+    /// Significance level for the Mann–Whitney U test
+    pub alpha: Option<f64>,
+    /// Thresholds per benchmark name regex, in addition to asv’s `regressions_thresholds`
+    pub benchmarks: BTreeMap<String, f64>,
+}
+
+impl RepoConfig {
+    /// Parse and validate the configuration.
+    pub fn parse(toml: &str) -> Result<Self> {
+        let config: Self =
+            toml::from_str(toml).with_context(|| format!("invalid {REPO_CONFIG_PATH}"))?;
+        config
+            .validate()
+            .with_context(|| format!("invalid {REPO_CONFIG_PATH}"))?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        ensure!(!self.label.trim().is_empty(), "`label` must not be empty");
+        if let Some(dir) = &self.benchmark_dir {
+            let is_relative = std::path::Path::new(dir)
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+            ensure!(
+                is_relative,
+                "`benchmark_dir` must be relative to the repository root, got {dir:?}"
+            );
+        }
+        if let Some(bench) = &self.bench {
+            Regex::new(bench).context("`bench` is not a valid regex")?;
+        }
+        for (name, timeout) in [
+            ("benchmark", self.timeouts.benchmark),
+            ("discover", self.timeouts.discover),
+            ("run", self.timeouts.run),
+            ("publish", self.timeouts.publish),
+        ] {
+            if timeout == Some(0) {
+                bail!("`timeouts.{name}` must be positive");
+            }
+        }
+        for (name, limit) in [("memory", self.limits.memory), ("cpu", self.limits.cpu)] {
+            if limit == Some(0) {
+                bail!("`limits.{name}` must be positive");
+            }
+        }
+        if let Some(default) = self.thresholds.default {
+            ensure!(default >= 0., "`thresholds.default` must not be negative");
+        }
+        if let Some(alpha) = self.thresholds.alpha {
+            ensure!(
+                0. < alpha && alpha < 1.,
+                "`thresholds.alpha` must be between 0 and 1"
+            );
+        }
+        Thresholds::new(&self.thresholds.benchmarks)?;
+        Ok(())
+    }
+
+    /// Read the configuration from a commit, or from the working directory if `commit` is `None`.
+    ///
+    /// Returns the default configuration if the file doesn’t exist.
+    pub fn read(repo: &git2::Repository, commit: Option<&str>) -> Result<Self> {
+        let content = if let Some(commit) = commit {
+            let tree = repo.revparse_single(commit)?.peel_to_tree()?;
+            let Ok(entry) = tree.get_path(std::path::Path::new(REPO_CONFIG_PATH)) else {
+                return Ok(Self::default());
+            };
+            let blob = entry.to_object(repo)?.peel_to_blob()?;
+            String::from_utf8(blob.content().to_owned())
+                .with_context(|| format!("{REPO_CONFIG_PATH} is not valid UTF-8"))?
+        } else {
+            let path = repo.workdir().context("no workdir")?.join(REPO_CONFIG_PATH);
+            if !path.is_file() {
+                return Ok(Self::default());
+            }
+            std::fs::read_to_string(path)?
+        };
+        Self::parse(&content)
+    }
+
+    /// Candidate paths of the asv config, relative to the repository root
+    pub fn asv_config_paths(&self) -> Vec<String> {
+        match &self.benchmark_dir {
+            Some(dir) => vec![format!("{}/asv.conf.json", dir.trim_end_matches('/'))],
+            None => ASV_CONFIG_PATHS.map(str::to_owned).to_vec(),
+        }
+    }
+
+    /// Thresholds for comparisons, combined with the ones from the asv config.
+    pub fn thresholds(&self, asv_config: &AsvConfig) -> Result<Thresholds> {
+        let mut per_benchmark = asv_config.regressions_thresholds.clone();
+        per_benchmark.extend(self.thresholds.benchmarks.clone());
+        let mut thresholds = Thresholds::new(&per_benchmark)?;
+        if let Some(default) = self.thresholds.default {
+            thresholds.default = default;
+        }
+        if let Some(alpha) = self.thresholds.alpha {
+            thresholds.alpha = alpha;
+        }
+        Ok(thresholds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    use crate::fixtures::commit;
+
+    #[test]
+    fn test_parse_empty() {
+        assert_eq!(RepoConfig::parse("").unwrap(), RepoConfig::default());
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = RepoConfig::parse(
+            r#"
+            label = "perf"
+            benchmark_dir = "bench/"
+            envs = ["py3.12"]
+            comments = "new"
+            [timeouts]
+            benchmark = 300
+            run = 3600
+            [limits]
+            memory = 4096
+            [thresholds]
+            alpha = 0.05
+            benchmarks = { "slow\\..*" = 0.5 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.label, "perf");
+        assert_eq!(config.comments, Comments::New);
+        assert_eq!(config.asv_config_paths(), vec!["bench/asv.conf.json"]);
+        assert_eq!(config.timeouts.benchmark, Some(300));
+        assert_eq!(
+            config.timeouts.for_phase(Phase::Run),
+            Duration::from_hours(1)
+        );
+        assert_eq!(
+            config.timeouts.for_phase(Phase::Discover),
+            Duration::from_mins(10)
+        );
+        assert_eq!(config.limits.memory, Some(4096));
+        assert_eq!(config.limits.cpu, None);
+
+        let asv_config = AsvConfig::parse(r#"{"regressions_thresholds": {"fast": 0.01}}"#).unwrap();
+        let thresholds = config.thresholds(&asv_config).unwrap();
+        assert!((thresholds.for_benchmark("slow.time_x") - 0.5).abs() < f64::EPSILON);
+        assert!((thresholds.for_benchmark("fast.time_x") - 0.01).abs() < f64::EPSILON);
+        assert!((thresholds.alpha - 0.05).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_read_from_commit() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let configs = tmp_dir.used_by(|p| {
+            let repo = git2::Repository::init(p).unwrap();
+            let files = [(REPO_CONFIG_PATH, "label = \"perf\"")];
+            let commit = commit(&repo, &files, "config", &[]).to_string();
+            // uncommitted changes are only visible in the working directory
+            std::fs::write(p.join(REPO_CONFIG_PATH), "label = \"other\"").unwrap();
+            (
+                RepoConfig::read(&repo, Some(&commit)).unwrap(),
+                RepoConfig::read(&repo, None).unwrap(),
+            )
+        });
+        let (from_commit, from_workdir) = &*configs;
+        assert_eq!(from_commit.label, "perf");
+        assert_eq!(from_workdir.label, "other");
+    }
+
+    #[rstest]
+    #[case::unknown_field("labels = []", "unknown field `labels`")]
+    #[case::wrong_type("envs = \"py3.12\"", "invalid type")]
+    #[case::empty_label("label = \"\"", "`label` must not be empty")]
+    #[case::absolute_dir("benchmark_dir = \"/etc\"", "`benchmark_dir` must be relative")]
+    #[case::parent_dir("benchmark_dir = \"../x\"", "`benchmark_dir` must be relative")]
+    #[case::bench("bench = \"(\"", "`bench` is not a valid regex")]
+    #[case::comments("comments = \"all\"", "unknown variant `all`")]
+    #[case::timeout("timeouts.benchmark = 0", "`timeouts.benchmark` must be positive")]
+    #[case::run_timeout("timeouts.run = 0", "`timeouts.run` must be positive")]
+    #[case::limit("limits.cpu = 0", "`limits.cpu` must be positive")]
+    #[case::alpha("thresholds.alpha = 1.5", "`thresholds.alpha` must be between 0 and 1")]
+    #[case::threshold_regex(
+        "thresholds.benchmarks = { \"(\" = 0.1 }",
+        "invalid benchmark name regex"
+    )]
+    fn test_parse_invalid(#[case] toml: &str, #[case] expected: &str) {
+        let err = format!("{:#}", RepoConfig::parse(toml).unwrap_err());
+        assert!(err.starts_with("invalid .github/benchmark.toml"), "{err}");
+        assert!(err.contains(expected), "{err}");
+    }
+}
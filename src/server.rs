@@ -1,33 +1,51 @@
 use anyhow::Result;
-use futures::FutureExt;
-use futures::{channel::mpsc::channel, TryFutureExt};
+use futures::TryFutureExt;
 use std::future::IntoFuture;
+use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio::task::JoinSet;
 
 use crate::cli::ServeArgs;
-use crate::event::Event;
+use crate::dirs::DATA_DIR;
+use crate::history::History;
+use crate::octocrab_utils::GitHubClient;
 use crate::utils::get_credential;
 
+pub(crate) mod admin;
 mod listener;
 mod octocrab_utils;
+mod queue;
 mod runner;
+pub(crate) mod trust;
 
-pub(crate) async fn serve(args: ServeArgs) -> Result<()> {
-    let (sender, receiver) = channel::<Event>(32);
+pub(crate) async fn serve(args: ServeArgs, github: GitHubClient) -> Result<()> {
+    let github = Arc::new(github);
+    let queue = Arc::new(queue::Queue::open(DATA_DIR.join("queue.json"))?);
+    tracing::info!("Restored {} queued jobs", queue.pending().len());
     // If secret has not been passed via CLI or env, get it as a credential.
     let secret_token = args
         .secret_token
         .ok_or(())
         .or_else(|()| get_credential("webhook_secret"))?;
 
-    let service = listener::listen(sender, secret_token);
+    let history = Arc::new(History::open(&History::default_path())?);
+    let service = listener::listen(
+        queue.clone(),
+        github.clone(),
+        secret_token,
+        args.trust,
+        history.clone(),
+    );
     let tcp_listener = TcpListener::bind(&args.addr).await?;
     tracing::info!("Listening on {}", args.addr);
+    let admin_path = admin::socket_path();
+    let admin_listener = admin::bind(&admin_path)?;
+    tracing::info!("Listening for queue commands on {}", admin_path.display());
 
     let mut set: JoinSet<Result<()>> = JoinSet::new();
     set.spawn(axum::serve(tcp_listener, service).into_future().err_into());
-    set.spawn(runner::runner(receiver).map(Result::Ok));
+    set.spawn(admin::listen(queue.clone(), github.clone(), admin_listener));
+    set.spawn(runner::runner(queue, github, history, args.public_url));
     while let Some(res) = set.join_next().await {
         let _ = res?;
     }
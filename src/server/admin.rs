@@ -0,0 +1,379 @@
+//! Managing the queue of a running server from the command line, see `benchmark queue`.
+//!
+//! The server listens on a Unix socket that only the user running it can connect to.
+//! Each connection carries one [`QueueCommand`] and its response as lines of JSON.
+use std::fmt::Write;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::dirs::DATA_DIR;
+use crate::event::Event;
+use crate::octocrab_utils::GitHubClient;
+use crate::utils::format_duration;
+
+use super::queue::{CancelReason, JobId, Queue, Status};
+use super::runner::{conclude_cancelled, requeue_check_run};
+
+/// How long to wait before accepting connections again after failing to, e.g. when out of file descriptors.
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Where the server listens for queue commands.
+pub(crate) fn socket_path() -> PathBuf {
+    DATA_DIR.join("admin.sock")
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum QueueCommand {
+    /// Show the running, pending, and recently finished jobs
+    List,
+    /// Cancel a pending or running job
+    Cancel { id: JobId },
+    /// Queue a finished job again, e.g. one that errored
+    Retry { id: JobId },
+    /// Stop starting pending jobs, the running job continues
+    Pause,
+    /// Start pending jobs again
+    Resume,
+}
+
+/// What the server sends back for a [`QueueCommand`].
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "reply", rename_all = "snake_case")]
+pub(crate) enum Reply {
+    Status(Box<Status>),
+    Done { message: String },
+}
+
+/// Listen on `path`, which only the current user may connect to.
+///
+/// Fails if another server is already listening there.
+pub(crate) fn bind(path: &Path) -> Result<UnixListener> {
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        bail!("Another server is already listening on {}", path.display());
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // a leftover from a server that didn’t shut down cleanly
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("failed to listen on {}", path.display()))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Handle queue commands sent to `listener`.
+pub(crate) async fn listen(
+    queue: Arc<Queue>,
+    github: Arc<GitHubClient>,
+    listener: UnixListener,
+) -> Result<()> {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                tracing::error!("Failed to accept admin connection: {e:?}");
+                tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let queue = queue.clone();
+        let github = github.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(&queue, &github, stream).await {
+                tracing::error!("Admin connection failed: {e:?}");
+            }
+        });
+    }
+}
+
+async fn serve_connection(queue: &Queue, github: &GitHubClient, stream: UnixStream) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    if BufReader::new(reader).read_line(&mut line).await? == 0 {
+        // e.g. another server checking if we’re still here
+        return Ok(());
+    }
+    let response: Result<Reply, String> = match serde_json::from_str(&line) {
+        Ok(command) => {
+            tracing::info!("Queue command: {command:?}");
+            handle(queue, github, command).await.map_err(|e| {
+                tracing::warn!("Queue command failed: {e:?}");
+                format!("{e:#}")
+            })
+        }
+        Err(e) => Err(format!("Invalid queue command: {e}")),
+    };
+    let mut json = serde_json::to_string(&response)?;
+    json.push('\n');
+    writer.write_all(json.as_bytes()).await?;
+    Ok(())
+}
+
+async fn handle(queue: &Queue, github: &GitHubClient, command: QueueCommand) -> Result<Reply> {
+    let message = match command {
+        QueueCommand::List => return Ok(Reply::Status(Box::new(queue.status()))),
+        QueueCommand::Cancel { id } => {
+            let reason = CancelReason::Operator;
+            let (cancelled, running) = queue.cancel(&reason, |job| job.id == id)?;
+            for job in &cancelled {
+                if let Err(e) = conclude_cancelled(github, &job.event, &reason).await {
+                    tracing::error!("Failed to conclude check run: {e:?}");
+                }
+            }
+            if running {
+                format!("Stopping job {id}")
+            } else if cancelled.is_empty() {
+                bail!("Job {id} is neither pending nor running");
+            } else {
+                format!("Cancelled job {id}")
+            }
+        }
+        QueueCommand::Retry { id } => {
+            let Some(finished) = queue.status().recent.into_iter().find(|f| f.job.id == id) else {
+                bail!("Job {id} isn’t among the recently finished jobs");
+            };
+            let mut event = finished.job.event;
+            let check_run_error = requeue_check_run(github, &mut event).await.err();
+            if let Some(e) = &check_run_error {
+                tracing::error!("Failed to create check run: {e:?}");
+                // don’t update the completed one
+                match &mut event {
+                    Event::Compare(cmp) => cmp.check_id = None,
+                    Event::Run(run) => run.check_id = None,
+                }
+            }
+            let (new_id, superseded) = queue.push(event)?;
+            for job in superseded {
+                let reason = CancelReason::Superseded {
+                    by: format!("job {new_id}"),
+                };
+                if let Err(e) = conclude_cancelled(github, &job.event, &reason).await {
+                    tracing::error!("Failed to conclude check run: {e:?}");
+                }
+            }
+            let mut message = format!("Queued job {id} again as job {new_id}");
+            if let Some(e) = check_run_error {
+                let _ = write!(
+                    message,
+                    ", without a check run: failed to create one: {e:#}"
+                );
+            }
+            message
+        }
+        QueueCommand::Pause => {
+            queue.set_paused(true)?;
+            "Paused: pending jobs won’t start until the queue is resumed".to_owned()
+        }
+        QueueCommand::Resume => {
+            queue.set_paused(false)?;
+            "Resumed".to_owned()
+        }
+    };
+    Ok(Reply::Done { message })
+}
+
+/// Send a command to the server listening on `path`.
+pub(crate) async fn request(path: &Path, command: &QueueCommand) -> Result<Reply> {
+    let stream = UnixStream::connect(path).await.with_context(|| {
+        format!(
+            "failed to connect to {}, is `benchmark serve` running as this user?",
+            path.display()
+        )
+    })?;
+    let (reader, mut writer) = stream.into_split();
+    let mut json = serde_json::to_string(command)?;
+    json.push('\n');
+    writer.write_all(json.as_bytes()).await?;
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+    let response: Result<Reply, String> =
+        serde_json::from_str(&line).context("invalid response from server")?;
+    response.map_err(anyhow::Error::msg)
+}
+
+/// Render the queue like the dashboard, as plain text.
+pub(crate) fn format_status(status: &Status) -> String {
+    let mut out = String::new();
+    if status.paused {
+        out.push_str("Paused: pending jobs won’t start until `benchmark queue resume`\n\n");
+    }
+    out.push_str("Running:\n");
+    if let Some(running) = &status.running {
+        let _ = writeln!(
+            out,
+            "  {:<6} {}, running for {}",
+            running.job.id.to_string(),
+            running.job.event,
+            format_duration(running.elapsed_seconds)
+        );
+    } else {
+        out.push_str("  Idle.\n");
+    }
+    let _ = writeln!(out, "\nPending ({}):", status.pending.len());
+    if status.pending.is_empty() {
+        out.push_str("  No jobs waiting.\n");
+    }
+    for job in &status.pending {
+        let _ = writeln!(
+            out,
+            "  {:<6} {}, enqueued {}",
+            job.id.to_string(),
+            job.event,
+            timestamp(job.enqueued_at)
+        );
+    }
+    out.push_str("\nRecent:\n");
+    if status.recent.is_empty() {
+        out.push_str("  No jobs finished yet.\n");
+    }
+    for finished in &status.recent {
+        let _ = writeln!(
+            out,
+            "  {:<6} {}, finished {}: {}",
+            finished.job.id.to_string(),
+            finished.job.event,
+            timestamp(finished.finished_at),
+            finished.outcome
+        );
+    }
+    out
+}
+
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::event::Run;
+    use crate::server::queue::Outcome;
+
+    fn run(commit: &str) -> Event {
+        Run {
+            owner: "scverse".to_owned(),
+            repo: "repo".to_owned(),
+            installation: None,
+            branch: "main".to_owned(),
+            commit: commit.to_owned(),
+            check_id: None,
+        }
+        .into()
+    }
+
+    fn github() -> Arc<GitHubClient> {
+        Arc::new(GitHubClient::plain(octocrab::Octocrab::default()))
+    }
+
+    fn done(reply: Reply) -> String {
+        match reply {
+            Reply::Done { message } => message,
+            Reply::Status(status) => panic!("expected a message, got {status:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let dir = tmp_dir.as_path_untracked();
+        let queue = Arc::new(Queue::open(dir.join("queue.json")).unwrap());
+        let path = dir.join("admin.sock");
+        tokio::spawn(listen(queue.clone(), github(), bind(&path).unwrap()));
+        // the socket is only for us
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(bind(&path).is_err());
+
+        let (id, _) = queue.push(run("a")).unwrap();
+        let Reply::Status(status) = request(&path, &QueueCommand::List).await.unwrap() else {
+            panic!("expected a status");
+        };
+        assert_eq!(status.pending[0].id, id);
+
+        let cancel = QueueCommand::Cancel { id };
+        let message = done(request(&path, &cancel).await.unwrap());
+        assert_eq!(message, "Cancelled job #0");
+        let error = request(&path, &cancel).await.unwrap_err();
+        assert_eq!(error.to_string(), "Job #0 is neither pending nor running");
+
+        let (id, _) = queue.push(run("b")).unwrap();
+        let (_, cancelled) = queue.next().await.unwrap();
+        let message = done(request(&path, &QueueCommand::Cancel { id }).await.unwrap());
+        assert_eq!(message, "Stopping job #1");
+        assert_eq!(cancelled.await, Ok(CancelReason::Operator));
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let queue = Queue::open(tmp_dir.as_path_untracked().join("queue.json")).unwrap();
+        let (id, _) = queue.push(run("a")).unwrap();
+        let error = handle(&queue, &github(), QueueCommand::Retry { id })
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Job #0 isn’t among the recently finished jobs"
+        );
+
+        let (job, _) = queue.next().await.unwrap();
+        let message = "oops".to_owned();
+        queue.finish(job.id, Outcome::Errored { message }).unwrap();
+        let message = done(
+            handle(&queue, &github(), QueueCommand::Retry { id })
+                .await
+                .unwrap(),
+        );
+        assert_eq!(message, "Queued job #0 again as job #1");
+        let pending: Vec<_> = queue.pending().into_iter().map(|job| job.event).collect();
+        assert_eq!(pending, vec![run("a")]);
+    }
+
+    #[tokio::test]
+    async fn test_pause_resume() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let queue = Queue::open(tmp_dir.as_path_untracked().join("queue.json")).unwrap();
+        handle(&queue, &github(), QueueCommand::Pause)
+            .await
+            .unwrap();
+        assert!(queue.status().paused);
+        let message = done(
+            handle(&queue, &github(), QueueCommand::Resume)
+                .await
+                .unwrap(),
+        );
+        assert_eq!(message, "Resumed");
+        assert!(!queue.status().paused);
+    }
+
+    #[tokio::test]
+    async fn test_format_status() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let queue = Queue::open(tmp_dir.as_path_untracked().join("queue.json")).unwrap();
+        queue.push(run("0123456789")).unwrap();
+        queue.push(run("abcdefghij")).unwrap();
+        let (job, _) = queue.next().await.unwrap();
+        queue.finish(job.id, Outcome::Failed).unwrap();
+        queue.set_paused(true).unwrap();
+
+        let text = format_status(&queue.status());
+        assert!(text.starts_with("Paused: "));
+        assert!(text.contains("Running:\n  Idle.\n"));
+        assert!(text.contains("Pending (1):\n  #1     scverse/repo@main at abcdefgh, enqueued "));
+        assert!(text.ends_with(": Some benchmarks failed\n"));
+    }
+}
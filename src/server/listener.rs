@@ -1,32 +1,54 @@
 use anyhow::{Context, Result};
-use futures::{channel::mpsc::Sender, SinkExt};
 use secrecy::{ExposeSecret, SecretString};
+use std::path::Path;
 use std::sync::Arc;
 
 use axum::{
     extract::{FromRef, State},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::post,
+    http::{HeaderMap, StatusCode},
+    routing::get,
     Router,
 };
 use axum_github_webhook_extract::{GithubEvent, GithubToken as GitHubSecret};
 use octocrab::models::webhook_events::payload::{
-    PullRequestWebhookEventAction as ActionType, PullRequestWebhookEventPayload as PullRequestEvent,
+    IssueCommentWebhookEventAction as CommentActionType,
+    IssueCommentWebhookEventPayload as IssueCommentEvent,
+    PullRequestWebhookEventAction as ActionType,
+    PullRequestWebhookEventPayload as PullRequestEvent, PushWebhookEventPayload as PushEvent,
 };
-use octocrab::{models::Repository, params::checks::CheckRunStatus, Octocrab};
-use tower_http::trace::TraceLayer;
+use octocrab::models::{reactions::ReactionContent, CheckRunId, InstallationId};
+use octocrab::params::checks::{CheckRunConclusion, CheckRunOutput, CheckRunStatus};
+use octocrab::{models::pulls::PullRequest, Octocrab};
+use serde::{de::DeserializeOwned, Deserialize};
+use tower_http::{services::ServeDir, trace::TraceLayer};
 
-use crate::constants::{BENCHMARK_LABEL, ORG};
-use crate::event::{Compare, Event};
+use crate::benchmark::{sites_dir, AsvConfig};
+use crate::constants::{CANCEL_ACTION, RERUN_ACTION};
+use crate::event::{Compare, Event, Filter, Fork, Run};
+use crate::history::History;
+use crate::octocrab_utils::GitHubClient;
+use crate::repo_config::{RepoConfig, REPO_CONFIG_PATH};
 
-use super::octocrab_utils::ref_exists;
+use super::octocrab_utils::{get_file, ref_exists};
+use super::queue::{CancelReason, Queue};
+use super::runner::{self, charts_dir, conclude_cancelled, logs_dir};
+use super::trust::TrustPolicy;
+
+use command::SlashCommand;
+
+mod command;
+mod history;
+mod status;
 
 #[derive(Debug, Clone)]
 struct AppState {
-    sender: Sender<Event>,
+    queue: Arc<Queue>,
     secret: GitHubSecret,
+    github: Arc<GitHubClient>,
+    /// Acts as the app installation for the repository a webhook event is about, once known
     github_client: Arc<Octocrab>,
+    trust: Arc<TrustPolicy>,
+    history: Arc<History>,
 }
 
 impl FromRef<AppState> for GitHubSecret {
@@ -35,107 +57,742 @@ impl FromRef<AppState> for GitHubSecret {
     }
 }
 
+type HandlerResult = Result<String, (StatusCode, String)>;
+
+/// Fields shared by all webhook payloads.
+#[derive(Debug, Deserialize)]
+struct Envelope {
+    /// The user who caused the event, e.g. by pushing, labelling, or commenting
+    sender: Option<User>,
+    repository: Option<RepositoryInfo>,
+    /// The app installation that received the event, if sent to an app
+    installation: Option<InstallationInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RepositoryInfo {
+    name: String,
+    owner: User,
+    default_branch: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InstallationInfo {
+    id: InstallationId,
+}
+
+/// The repository an event is about, and the app installation that received the event.
+#[derive(Debug, Clone)]
+struct Target {
+    owner: String,
+    repo: String,
+    installation: Option<InstallationId>,
+}
+
+impl Target {
+    /// Whether `cmp` is a job for this target’s `pr`.
+    fn is_pr(&self, cmp: &Compare, pr: u64) -> bool {
+        cmp.owner == self.owner && cmp.repo == self.repo && cmp.pr == pr
+    }
+}
+
+/// Dispatch a webhook delivery by its event type.
+///
+/// Handlers act as the app installation that received the event.
 async fn handle(
-    State(state): State<AppState>,
-    GithubEvent(PullRequestEvent {
+    State(mut state): State<AppState>,
+    headers: HeaderMap,
+    GithubEvent(payload): GithubEvent<serde_json::Value>,
+) -> HandlerResult {
+    let event_type = headers
+        .get("X-GitHub-Event")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("pull_request");
+    if !matches!(
+        event_type,
+        "pull_request" | "issue_comment" | "push" | "check_run" | "check_suite"
+    ) {
+        return Ok(format!("skipped: {event_type} event"));
+    }
+    let Envelope {
+        sender,
+        repository,
+        installation,
+    } = Envelope::deserialize(&payload).map_err(|e| {
+        tracing::error!("Invalid payload: {e}");
+        (StatusCode::BAD_REQUEST, e.to_string())
+    })?;
+    let Some(RepositoryInfo {
+        name: repo,
+        owner,
+        default_branch,
+    }) = repository
+    else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "missing field `repository`".to_owned(),
+        ));
+    };
+    let target = Target {
+        owner: owner.login,
+        repo,
+        installation: installation.map(|i| i.id),
+    };
+    state.github_client = state
+        .github
+        .installation(&target.owner, &target.repo, target.installation)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to authenticate as installation: {e:?}");
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        })?;
+    let sender = sender.map(|u| u.login);
+    match event_type {
+        "pull_request" => Box::pin(handle_pr(state, target, sender, parse_payload(payload)?)).await,
+        "issue_comment" => Box::pin(handle_comment(state, target, parse_payload(payload)?)).await,
+        "check_run" => {
+            let payload = parse_payload(payload)?;
+            Box::pin(handle_check_run(state, target, sender, payload)).await
+        }
+        "check_suite" => {
+            let payload = parse_payload(payload)?;
+            Box::pin(handle_check_suite(state, target, sender, payload)).await
+        }
+        _ => {
+            let payload = parse_payload(payload)?;
+            Box::pin(handle_push(state, target, default_branch, payload)).await
+        }
+    }
+}
+
+fn parse_payload<T: DeserializeOwned>(
+    payload: serde_json::Value,
+) -> Result<T, (StatusCode, String)> {
+    serde_json::from_value(payload).map_err(|e| {
+        tracing::error!("Invalid payload: {e}");
+        (StatusCode::BAD_REQUEST, e.to_string())
+    })
+}
+
+async fn handle_pr(
+    state: AppState,
+    target: Target,
+    sender: Option<String>,
+    PullRequestEvent {
         pull_request: pr,
         action,
         label,
         ..
-    }): GithubEvent<PullRequestEvent>,
-) -> impl IntoResponse {
-    if !matches!(
+    }: PullRequestEvent,
+) -> HandlerResult {
+    match action {
+        ActionType::Opened
+        | ActionType::Reopened
+        | ActionType::Synchronize
+        | ActionType::Labeled
+        | ActionType::Unlabeled => {}
+        ActionType::Closed => {
+            return handle_cancel(&state, &target, pr.number, CancelReason::Closed).await;
+        }
+        _ => return Ok("skipped: event action".to_owned()),
+    }
+    let config = repo_config(&state, &target, &pr.base.ref_field).await?;
+    if matches!(action, ActionType::Labeled | ActionType::Unlabeled) {
+        let label = label.ok_or_else(|| (StatusCode::BAD_REQUEST, "missing label".to_owned()))?;
+        if label.name != config.label {
+            return Ok(format!("skipped: label is not {}", config.label));
+        }
+        if matches!(action, ActionType::Unlabeled) {
+            return handle_cancel(&state, &target, pr.number, CancelReason::Unlabeled).await;
+        }
+    }
+    if pr.labels.iter().flatten().all(|e| e.name != config.label) {
+        return Ok(format!("skipped: missing {} label", config.label));
+    }
+    // Whoever labelled or pushed vouches for the PR’s code, fall back to the PR author
+    let Some(user) = sender.or_else(|| pr.user.as_ref().map(|u| u.login.clone())) else {
+        return Err((StatusCode::BAD_REQUEST, "missing sender".to_owned()));
+    };
+    if !check_trust(&state, &target, &user).await? {
+        refuse(&state, &target, &pr.head.sha, &user).await;
+        return Ok(format!("skipped: {user} is not trusted"));
+    }
+
+    let check_id = create_check_run(&state, &target, &pr.head.sha, Some(pr.number)).await;
+    let fork = fork(&pr);
+    handle_enqueue(
+        Compare {
+            owner: target.owner,
+            repo: target.repo,
+            installation: target.installation,
+            commits: [pr.base.sha, pr.head.sha],
+            pr: pr.number,
+            fork,
+            check_id,
+            filter: Filter::default(),
+        },
+        state,
+    )
+    .await
+}
+
+/// Benchmark commits pushed to tracked branches to build up a history.
+///
+/// Tracked are the default branch and the `branches` in the asv config.
+/// Pushing to the repository requires write access, so there’s no need to check trust.
+async fn handle_push(
+    state: AppState,
+    target: Target,
+    default_branch: Option<String>,
+    PushEvent {
+        r#ref,
+        after,
+        deleted,
+        ..
+    }: PushEvent,
+) -> HandlerResult {
+    if deleted {
+        return Ok("skipped: branch deleted".to_owned());
+    }
+    let Some(branch) = r#ref.strip_prefix("refs/heads/") else {
+        return Ok("skipped: not a branch".to_owned());
+    };
+    if default_branch.as_deref() != Some(branch)
+        && !asv_branches(&state, &target, &after)
+            .await?
+            .iter()
+            .any(|b| b == branch)
+    {
+        return Ok("skipped: branch is not benchmarked".to_owned());
+    }
+
+    let check_id = create_check_run(&state, &target, &after, None).await;
+    let run = Run {
+        owner: target.owner,
+        repo: target.repo,
+        installation: target.installation,
+        branch: branch.to_owned(),
+        commit: after,
+        check_id,
+    };
+    let (id, _) = state.queue.push(run.into()).map_err(|e| {
+        tracing::error!("Enqueue failed: {e:?}");
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })?;
+    tracing::info!("Enqueued job {id}");
+    Ok("enqueued".to_owned())
+}
+
+/// Get the branches asv tracks from the asv config at `git_ref`.
+async fn asv_branches(
+    state: &AppState,
+    target: &Target,
+    git_ref: &str,
+) -> Result<Vec<String>, (StatusCode, String)> {
+    let config = repo_config(state, target, git_ref).await?;
+    for path in config.asv_config_paths() {
+        let content = get_file(
+            &state.github_client,
+            &target.owner,
+            &target.repo,
+            &path,
+            git_ref,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to get asv config: {e:?}");
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        })?;
+        if let Some(content) = content {
+            let config = AsvConfig::parse(&content)
+                .map_err(|e| (StatusCode::BAD_REQUEST, format!("{e:#}")))?;
+            return Ok(config.branches);
+        }
+    }
+    Ok(vec![])
+}
+
+/// Get the repository configuration at `git_ref`.
+///
+/// Falls back to the default configuration if it’s invalid,
+/// the runner reports the error in the check run.
+async fn repo_config(
+    state: &AppState,
+    Target { owner, repo, .. }: &Target,
+    git_ref: &str,
+) -> Result<RepoConfig, (StatusCode, String)> {
+    let content = get_file(&state.github_client, owner, repo, REPO_CONFIG_PATH, git_ref)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to get repository config: {e:?}");
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        })?;
+    let Some(content) = content else {
+        return Ok(RepoConfig::default());
+    };
+    Ok(RepoConfig::parse(&content).unwrap_or_else(|e| {
+        tracing::warn!("Using default config for {owner}/{repo}: {e:#}");
+        RepoConfig::default()
+    }))
+}
+
+/// Handle slash commands in PR comments, and acknowledge them with a reaction.
+async fn handle_comment(
+    state: AppState,
+    target: Target,
+    IssueCommentEvent {
         action,
-        ActionType::Opened | ActionType::Reopened | ActionType::Synchronize | ActionType::Labeled
-    ) {
+        comment,
+        issue,
+        ..
+    }: IssueCommentEvent,
+) -> HandlerResult {
+    if !matches!(action, CommentActionType::Created) {
         return Ok("skipped: event action".to_owned());
     }
-    if matches!(action, ActionType::Labeled)
-        && label
-            .ok_or_else(|| (StatusCode::BAD_REQUEST, "missing label".to_owned()))?
-            .name
-            != BENCHMARK_LABEL
-    {
-        return Ok("skipped: added label is not benchmark".to_owned());
+    if issue.pull_request.is_none() {
+        return Ok("skipped: not a PR".to_owned());
     }
-    if pr
-        .labels
-        .iter()
-        .flatten()
-        .all(|e| e.name != BENCHMARK_LABEL)
+    let Some(command) = comment.body.as_deref().and_then(SlashCommand::parse) else {
+        return Ok("skipped: no command".to_owned());
+    };
+    let user = &comment.user.login;
+    let command = if check_trust(&state, &target, user).await? {
+        command
+    } else {
+        Err(format!("{user} is not trusted"))
+    };
+    let (res, reaction) = match command {
+        Ok(command) => {
+            let res = handle_command(&state, command, user, &target, issue.number).await;
+            let reaction = if res.is_ok() {
+                ReactionContent::PlusOne
+            } else {
+                ReactionContent::Confused
+            };
+            (res, reaction)
+        }
+        Err(msg) => (Ok(format!("skipped: {msg}")), ReactionContent::Confused),
+    };
+    if let Err(e) = state
+        .github_client
+        .issues(&target.owner, &target.repo)
+        .create_comment_reaction(comment.id, reaction)
+        .await
     {
-        return Ok("skipped: missing benchmark label".to_owned());
+        tracing::error!("Failed to react to comment: {e:?}");
     }
-    let Some(Repository { name: repo, .. }) = pr.base.repo else {
-        return Err((StatusCode::BAD_REQUEST, "missing repo".to_owned()));
-    };
+    res
+}
 
-    let github_client = octocrab::instance();
-    let checks = github_client.checks(ORG, &repo);
-    // `.ok()` allows creating the check run creation to fail. We’ll not try to update it in that case.
-    let check_id = checks
-        .create_check_run("benchmark", &pr.head.sha)
-        .status(CheckRunStatus::Queued)
-        .send()
+/// Run a command that `user` issued for a PR.
+#[tracing::instrument(skip(state))]
+async fn handle_command(
+    state: &AppState,
+    command: SlashCommand,
+    user: &str,
+    target: &Target,
+    pr: u64,
+) -> HandlerResult {
+    let filter = match command {
+        SlashCommand::Cancel => {
+            let reason = CancelReason::Requested {
+                by: user.to_owned(),
+            };
+            return handle_cancel(state, target, pr, reason).await;
+        }
+        SlashCommand::Rerun => {
+            let reason = CancelReason::Requested {
+                by: user.to_owned(),
+            };
+            cancel_where(state, &reason, |cmp| target.is_pr(cmp, pr)).await?;
+            Filter::default()
+        }
+        SlashCommand::Run(filter) => filter,
+    };
+    let pr = state
+        .github_client
+        .pulls(&target.owner, &target.repo)
+        .get(pr)
         .await
-        .map(|c| c.id)
-        .context("Failed to create check run")
-        .map_err(|e| tracing::error!("{e:?}"))
-        .ok();
+        .map_err(|e| {
+            tracing::error!("Failed to get PR: {e:?}");
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        })?;
+    let check_id = create_check_run(state, target, &pr.head.sha, Some(pr.number)).await;
+    let fork = fork(&pr);
     handle_enqueue(
         Compare {
-            repo,
+            owner: target.owner.clone(),
+            repo: target.repo.clone(),
+            installation: target.installation,
             commits: [pr.base.sha, pr.head.sha],
             pr: pr.number,
+            fork,
             check_id,
+            filter,
         },
-        state,
+        state.clone(),
     )
     .await
 }
 
-#[tracing::instrument(skip_all, fields(repo = %event.repo, pr = %event.pr))]
-async fn handle_enqueue(
-    event: Compare,
-    mut state: AppState,
-) -> Result<String, (StatusCode, String)> {
-    let ref_exists = ref_exists(&state.github_client, &event.repo, &event.commits[1])
+/// The parts of a `check_run` webhook payload we need.
+///
+/// octocrab’s payload type lacks `requested_action`.
+#[derive(Debug, Deserialize)]
+struct CheckRunEvent {
+    action: String,
+    check_run: CheckRunInfo,
+    requested_action: Option<RequestedAction>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckRunInfo {
+    external_id: Option<String>,
+    #[serde(default)]
+    pull_requests: Vec<PullRequestInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PullRequestInfo {
+    number: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct RequestedAction {
+    identifier: String,
+}
+
+/// Handle the “Re-run benchmarks” and “Cancel” buttons of check runs, and GitHub’s “Re-run” button.
+async fn handle_check_run(
+    state: AppState,
+    target: Target,
+    sender: Option<String>,
+    CheckRunEvent {
+        action,
+        check_run,
+        requested_action,
+    }: CheckRunEvent,
+) -> HandlerResult {
+    let command = match (action.as_str(), requested_action) {
+        ("rerequested", _) => SlashCommand::Rerun,
+        ("requested_action", Some(RequestedAction { identifier })) => match identifier.as_str() {
+            RERUN_ACTION => SlashCommand::Rerun,
+            CANCEL_ACTION => SlashCommand::Cancel,
+            _ => return Ok(format!("skipped: unknown action {identifier}")),
+        },
+        _ => return Ok("skipped: event action".to_owned()),
+    };
+    let Some(pr) = check_run
+        .external_id
+        .and_then(|id| id.parse().ok())
+        .or_else(|| check_run.pull_requests.first().map(|pr| pr.number))
+    else {
+        return Ok("skipped: not a PR check run".to_owned());
+    };
+    let Some(user) = sender else {
+        return Err((StatusCode::BAD_REQUEST, "missing sender".to_owned()));
+    };
+    if !check_trust(&state, &target, &user).await? {
+        return Ok(format!("skipped: {user} is not trusted"));
+    }
+    handle_command(&state, command, &user, &target, pr).await
+}
+
+/// The parts of a `check_suite` webhook payload we need.
+#[derive(Debug, Deserialize)]
+struct CheckSuiteEvent {
+    action: String,
+    check_suite: CheckSuiteInfo,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckSuiteInfo {
+    id: u64,
+    #[serde(default)]
+    pull_requests: Vec<PullRequestInfo>,
+}
+
+/// The check runs of a check suite, as listed by GitHub.
+#[derive(Debug, Deserialize)]
+struct CheckRunList {
+    check_runs: Vec<CheckRunInfo>,
+}
+
+/// Handle GitHub’s “Re-run all checks” button, which re-requests our whole check suite.
+///
+/// Each PR the suite belongs to is benchmarked again.
+async fn handle_check_suite(
+    state: AppState,
+    target: Target,
+    sender: Option<String>,
+    CheckSuiteEvent {
+        action,
+        check_suite,
+    }: CheckSuiteEvent,
+) -> HandlerResult {
+    if action != "rerequested" {
+        return Ok("skipped: event action".to_owned());
+    }
+    let mut prs: Vec<u64> = check_suite
+        .pull_requests
+        .iter()
+        .map(|pr| pr.number)
+        .collect();
+    if prs.is_empty() {
+        prs = suite_prs(&state, &target, check_suite.id).await?;
+    }
+    prs.sort_unstable();
+    prs.dedup();
+    if prs.is_empty() {
+        return Ok("skipped: not a PR check suite".to_owned());
+    }
+    let Some(user) = sender else {
+        return Err((StatusCode::BAD_REQUEST, "missing sender".to_owned()));
+    };
+    if !check_trust(&state, &target, &user).await? {
+        return Ok(format!("skipped: {user} is not trusted"));
+    }
+    let mut results = vec![];
+    for pr in prs {
+        let result = handle_command(&state, SlashCommand::Rerun, &user, &target, pr).await?;
+        results.push(format!("#{pr}: {result}"));
+    }
+    Ok(results.join("\n"))
+}
+
+/// Get the PRs a check suite’s benchmark check runs were created for.
+///
+/// GitHub doesn’t list PRs from forks in `check_suite` webhooks, but our check runs store them.
+async fn suite_prs(
+    state: &AppState,
+    target: &Target,
+    suite_id: u64,
+) -> Result<Vec<u64>, (StatusCode, String)> {
+    let route = format!(
+        "/repos/{}/{}/check-suites/{suite_id}/check-runs?check_name=benchmark",
+        target.owner, target.repo
+    );
+    let list: CheckRunList = state
+        .github_client
+        .get(route, None::<&()>)
         .await
         .map_err(|e| {
-            tracing::error!("Enqueue failed: {e:?}");
+            tracing::error!("Failed to list check runs: {e:?}");
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        })?;
+    Ok(list
+        .check_runs
+        .into_iter()
+        .filter_map(|run| run.external_id?.parse().ok())
+        .collect())
+}
+
+/// Get the repository a PR comes from, if it’s not the one it gets merged into.
+fn fork(pr: &PullRequest) -> Option<Fork> {
+    let head_repo = pr.head.repo.as_ref()?;
+    if pr
+        .base
+        .repo
+        .as_ref()
+        .is_some_and(|base| base.id == head_repo.id)
+    {
+        return None;
+    }
+    Some(Fork {
+        clone_url: head_repo.clone_url.as_ref()?.to_string(),
+        branch: pr.head.ref_field.clone(),
+    })
+}
+
+/// Whether `user` may run code on the benchmark machine.
+async fn check_trust(
+    state: &AppState,
+    target: &Target,
+    user: &str,
+) -> Result<bool, (StatusCode, String)> {
+    state
+        .trust
+        .is_trusted(&state.github_client, &target.owner, &target.repo, user)
+        .await
+        .map_err(|e| {
+            tracing::error!("Trust check failed: {e:?}");
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        })
+}
+
+/// Report a refused run via a neutral check run.
+async fn refuse(state: &AppState, target: &Target, head_sha: &str, user: &str) {
+    tracing::info!("Refusing to run benchmarks for untrusted user {user}");
+    if let Err(e) = state
+        .github_client
+        .checks(&target.owner, &target.repo)
+        .create_check_run("benchmark", head_sha)
+        .status(CheckRunStatus::Completed)
+        .conclusion(CheckRunConclusion::Neutral)
+        .output(CheckRunOutput {
+            title: "Benchmark".to_owned(),
+            summary: format!("Not running benchmarks: @{user} is not trusted to run code on the benchmark machine."),
+            text: Some("A maintainer can start a run with a `/benchmark` comment.".to_owned()),
+            annotations: vec![],
+            images: vec![],
+        })
+        .send()
+        .await
+    {
+        tracing::error!("Failed to create check run: {e:?}");
+    }
+}
+
+/// Create a queued check run, see [`runner::create_check_run`](super::runner::create_check_run).
+///
+/// Returns `None` if creating the check run fails. We’ll not try to update it in that case.
+async fn create_check_run(
+    state: &AppState,
+    target: &Target,
+    head_sha: &str,
+    pr: Option<u64>,
+) -> Option<CheckRunId> {
+    runner::create_check_run(
+        &state.github_client,
+        &target.owner,
+        &target.repo,
+        head_sha,
+        pr,
+    )
+    .await
+    .context("Failed to create check run")
+    .map_err(|e| tracing::error!("{e:?}"))
+    .ok()
+}
+
+/// Cancel all queued and running jobs for a PR.
+#[tracing::instrument(skip(state))]
+async fn handle_cancel(
+    state: &AppState,
+    target: &Target,
+    pr: u64,
+    reason: CancelReason,
+) -> HandlerResult {
+    cancel_where(state, &reason, |cmp| target.is_pr(cmp, pr)).await?;
+    Ok(format!("cancelled: {reason}"))
+}
+
+/// Cancel jobs for which `pred` is true and conclude check runs of the removed ones.
+async fn cancel_where(
+    state: &AppState,
+    reason: &CancelReason,
+    pred: impl Fn(&Compare) -> bool,
+) -> Result<(), (StatusCode, String)> {
+    let (cancelled, _) = state
+        .queue
+        .cancel(reason, |job| match &job.event {
+            Event::Compare(cmp) => pred(cmp),
+            Event::Run(_) => false,
+        })
+        .map_err(|e| {
+            tracing::error!("Cancel failed: {e:?}");
             (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
         })?;
+    for job in cancelled {
+        tracing::info!("Cancelled job {}: {reason}", job.id);
+        if let Err(e) = conclude_cancelled(&state.github, &job.event, reason).await {
+            tracing::error!("Failed to conclude check run: {e:?}");
+        }
+    }
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, fields(owner = %event.owner, repo = %event.repo, pr = %event.pr))]
+async fn handle_enqueue(event: Compare, state: AppState) -> HandlerResult {
+    let ref_exists = ref_exists(
+        &state.github_client,
+        &event.owner,
+        &event.repo,
+        &event.commits[1],
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!("Enqueue failed: {e:?}");
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })?;
     if ref_exists {
-        state
-            .sender
-            .send(event.into())
-            .await
-            .map(|()| "enqueued".to_owned())
-            .map_err(|_| {
-                let msg = "Failed to send event";
-                tracing::error!("Enqueue failed: {msg}");
-                (StatusCode::INTERNAL_SERVER_ERROR, msg.to_owned())
-            })
+        let (id, superseded) = state.queue.push(event.clone().into()).map_err(|e| {
+            tracing::error!("Enqueue failed: {e:?}");
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+        })?;
+        tracing::info!("Enqueued job {id}");
+        let reason = CancelReason::Superseded {
+            by: event.commits[1].clone(),
+        };
+        for job in superseded {
+            tracing::info!("Job {id} supersedes queued job {}", job.id);
+            if let Err(e) = conclude_cancelled(&state.github, &job.event, &reason).await {
+                tracing::error!("Failed to conclude check run: {e:?}");
+            }
+        }
+        // stop a running job for an outdated commit
+        cancel_where(&state, &reason, |cmp| {
+            cmp.owner == event.owner
+                && cmp.repo == event.repo
+                && cmp.pr == event.pr
+                && cmp.commits[1] != event.commits[1]
+        })
+        .await?;
+        Ok("enqueued".to_owned())
     } else {
         let msg = format!(
-            "{}/{} is not a valid repo/ref combination",
-            event.repo, event.commits[1]
+            "{}/{}/{} is not a valid repo/ref combination",
+            event.owner, event.repo, event.commits[1]
         );
         tracing::info!("Enqueue failed: {msg}");
         Err((StatusCode::BAD_REQUEST, msg))
     }
 }
 
-pub(crate) fn listen(sender: Sender<Event>, secret: SecretString) -> axum::Router {
+pub(crate) fn listen(
+    queue: Arc<Queue>,
+    github: Arc<GitHubClient>,
+    secret: SecretString,
+    trust: TrustPolicy,
+    history: Arc<History>,
+) -> axum::Router {
     let state = AppState {
-        sender,
+        queue,
         secret: GitHubSecret(Arc::new(secret.expose_secret().to_owned())),
-        github_client: octocrab::instance(),
+        github_client: github.client().clone(),
+        github,
+        trust: Arc::new(trust),
+        history,
     };
     std::mem::drop(secret);
+    router(state, &sites_dir(), &logs_dir(), &charts_dir())
+}
 
+/// Route webhook deliveries, status, and history requests,
+/// and serve the HTML sites published to `sites_dir`, the job logs in `logs_dir`,
+/// and the charts of comparisons in `charts_dir`.
+fn router(state: AppState, sites_dir: &Path, logs_dir: &Path, charts_dir: &Path) -> axum::Router {
     Router::new()
-        .route("/", post(handle))
+        .route("/", get(status::dashboard).post(handle))
+        .route("/api/status", get(status::status_json))
+        .route(
+            "/api/history/{owner}/{repo}/{benchmark}",
+            get(history::time_series),
+        )
+        .route(
+            "/api/compare/{owner}/{repo}/{before}/{after}",
+            get(history::compare),
+        )
+        .nest_service("/sites", ServeDir::new(sites_dir))
+        .nest_service("/logs", ServeDir::new(logs_dir))
+        .nest_service("/charts", ServeDir::new(charts_dir))
         .layer(TraceLayer::new_for_http())
         .with_state(state)
 }
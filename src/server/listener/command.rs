@@ -0,0 +1,88 @@
+use regex::Regex;
+
+use crate::event::Filter;
+
+const PREFIX: &str = "/benchmark";
+
+/// A command given in a PR comment, e.g. `/benchmark bench=time_.*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum SlashCommand {
+    /// `/benchmark [envs=<env>,…] [bench=<regex>]`: queue a run.
+    Run(Filter),
+    /// `/benchmark cancel`: cancel queued and running jobs.
+    Cancel,
+    /// `/benchmark rerun`: stop a running job and run again.
+    Rerun,
+}
+
+impl SlashCommand {
+    /// Find the first command in a comment. Returns `None` if the comment contains no command.
+    pub(super) fn parse(body: &str) -> Option<Result<Self, String>> {
+        let args = body.lines().find_map(|line| {
+            let mut words = line.split_whitespace();
+            (words.next() == Some(PREFIX)).then_some(words)
+        })?;
+        Some(Self::parse_args(args.collect()))
+    }
+
+    fn parse_args(args: Vec<&str>) -> Result<Self, String> {
+        match args.as_slice() {
+            ["cancel"] => return Ok(Self::Cancel),
+            ["rerun"] => return Ok(Self::Rerun),
+            _ => {}
+        }
+        let mut filter = Filter::default();
+        for arg in args {
+            match arg.split_once('=') {
+                Some(("envs", envs)) => {
+                    filter
+                        .envs
+                        .extend(envs.split(',').filter(|e| !e.is_empty()).map(str::to_owned));
+                }
+                Some(("bench", bench)) => {
+                    Regex::new(bench).map_err(|e| format!("invalid bench regex: {e}"))?;
+                    filter.bench = Some(bench.to_owned());
+                }
+                _ => return Err(format!("unknown argument `{arg}`")),
+            }
+        }
+        Ok(Self::Run(filter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("/benchmark", SlashCommand::Run(Filter::default()))]
+    #[case("LGTM!\r\n/benchmark cancel\r\n", SlashCommand::Cancel)]
+    #[case("/benchmark  rerun", SlashCommand::Rerun)]
+    #[case(
+        "/benchmark envs=py3.12,py3.13 bench=time_.*",
+        SlashCommand::Run(Filter {
+            envs: vec!["py3.12".to_owned(), "py3.13".to_owned()],
+            bench: Some("time_.*".to_owned()),
+        })
+    )]
+    fn test_parse(#[case] body: &str, #[case] expected: SlashCommand) {
+        assert_eq!(SlashCommand::parse(body), Some(Ok(expected)));
+    }
+
+    #[rstest]
+    #[case("/benchmark cancel now", "unknown argument `cancel`")]
+    #[case("/benchmark bench=(", "invalid bench regex")]
+    fn test_parse_error(#[case] body: &str, #[case] expected: &str) {
+        let err = SlashCommand::parse(body).unwrap().unwrap_err();
+        assert!(err.starts_with(expected), "{err}");
+    }
+
+    #[rstest]
+    #[case("Let’s run /benchmark later")]
+    #[case("/benchmarks")]
+    #[case("")]
+    fn test_parse_none(#[case] body: &str) {
+        assert_eq!(SlashCommand::parse(body), None);
+    }
+}
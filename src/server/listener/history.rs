@@ -0,0 +1,68 @@
+//! Past results: time series per benchmark and comparisons of arbitrary commits.
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Json,
+};
+
+use crate::benchmark::cached_thresholds;
+use crate::benchmark::comparison::Comparison;
+use crate::history::{CommitLookupError, History, NoResults, Point};
+
+use super::AppState;
+
+/// Get all recorded measurements of a benchmark, ordered by commit date.
+pub(super) async fn time_series(
+    State(state): State<AppState>,
+    Path((owner, repo, benchmark)): Path<(String, String, String)>,
+) -> Result<Json<Vec<Point>>, (StatusCode, String)> {
+    let points = query(state.history, move |history| {
+        history.time_series(&owner, &repo, &benchmark)
+    })
+    .await?;
+    if points.is_empty() {
+        return Err((StatusCode::NOT_FOUND, "no results recorded".to_owned()));
+    }
+    Ok(Json(points))
+}
+
+/// Compare two commits’ recorded results as Markdown, without running benchmarks.
+///
+/// Uses the thresholds configured in the server’s clone of the repository, like runs on its default branch do.
+pub(super) async fn compare(
+    State(state): State<AppState>,
+    Path((owner, repo, before, after)): Path<(String, String, String, String)>,
+) -> Result<String, (StatusCode, String)> {
+    let cmp = query(state.history, move |history| {
+        let thresholds = cached_thresholds(&owner, &repo)?;
+        Comparison::from_history(history, &owner, &repo, [&before, &after], &thresholds)
+    })
+    .await?;
+    Ok(cmp.markdown(false))
+}
+
+/// Run a query on the history without blocking the server.
+async fn query<T, F>(history: Arc<History>, f: F) -> Result<T, (StatusCode, String)>
+where
+    T: Send + 'static,
+    F: FnOnce(&History) -> anyhow::Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || f(&history))
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| (error_status(&e), format!("{e:#}")))
+}
+
+/// Tell missing results and bad commit prefixes apart from failures of the server.
+fn error_status(error: &anyhow::Error) -> StatusCode {
+    if error.downcast_ref::<NoResults>().is_some() {
+        StatusCode::NOT_FOUND
+    } else if error.downcast_ref::<CommitLookupError>().is_some() {
+        StatusCode::BAD_REQUEST
+    } else {
+        tracing::error!("History query failed: {error:?}");
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
@@ -0,0 +1,48 @@
+//! What the server is doing: the queue as JSON and as an HTML dashboard.
+use askama::Template;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{Html, Json},
+};
+use chrono::{DateTime, Utc};
+
+use crate::server::queue::{Outcome, Status};
+use crate::utils::format_duration;
+
+use super::AppState;
+
+/// Get the running, pending, and recently finished jobs.
+pub(super) async fn status_json(State(state): State<AppState>) -> Json<Status> {
+    Json(state.queue.status())
+}
+
+/// Render the queue status as an HTML page.
+pub(super) async fn dashboard(
+    State(state): State<AppState>,
+) -> Result<Html<String>, (StatusCode, String)> {
+    let status = state.queue.status();
+    Dashboard {
+        elapsed: status
+            .running
+            .as_ref()
+            .map(|running| format_duration(running.elapsed_seconds)),
+        status: &status,
+        now: Utc::now(),
+    }
+    .render()
+    .map(Html)
+    .map_err(|e| {
+        tracing::error!("Failed to render dashboard: {e:?}");
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })
+}
+
+#[derive(Template)]
+#[template(path = "status.html.j2", escape = "html")]
+struct Dashboard<'a> {
+    status: &'a Status,
+    /// How long the running job has been running
+    elapsed: Option<String>,
+    now: DateTime<Utc>,
+}
@@ -1,11 +1,12 @@
 use axum::{
-    body::Body, extract::Request, http::StatusCode, response::Response, routing::post, Router,
+    body::Body,
+    extract::Request,
+    http::{HeaderValue, StatusCode},
+    response::Response,
+    Router,
 };
 use axum_github_webhook_extract::GithubToken as GitHubSecret;
-use futures::{
-    channel::mpsc::{channel, Receiver},
-    StreamExt,
-};
+use base64::Engine as _;
 use hmac_sha256::HMAC;
 use http_body_util::BodyExt;
 use octocrab::{
@@ -13,17 +14,24 @@ use octocrab::{
     Octocrab,
 };
 use std::sync::Arc;
+use test_temp_dir::TestTempDir;
 use tower::ServiceExt;
 use wiremock::{
-    matchers::{method, path},
+    matchers::{body_partial_json, method, path, path_regex},
     Mock, MockServer, ResponseTemplate,
 };
 
+use crate::benchmark::results::{parse_benchmarks, Results};
 use crate::constants::ORG;
-use crate::event::{Compare, Event};
-use crate::fixtures::{COMMIT, PR};
+use crate::event::{Compare, Event, Filter, Fork, Run};
+use crate::fixtures::{
+    ASV_BENCHMARKS, ASV_RESULTS_AFTER, ASV_RESULTS_BEFORE, COMMENT, COMMIT, PR, PUSH,
+};
+use crate::history::History;
+use crate::octocrab_utils::GitHubClient;
+use crate::server::queue::{CancelReason, Outcome};
 
-use super::{handle, AppState};
+use super::{router, AppState, Queue, TrustPolicy};
 
 mod mock_error {
     use serde_json::json;
@@ -57,8 +65,17 @@ use mock_error::setup_error_handler;
 
 const TEST_SECRET: &str = "It's a Secret to Everybody";
 
-async fn setup_github_api(template: Option<ResponseTemplate>) -> MockServer {
+async fn setup_github_api(template: Option<ResponseTemplate>, mocks: Vec<Mock>) -> MockServer {
     let mock_server = MockServer::start().await;
+    for mock in mocks {
+        mock.mount(&mock_server).await;
+    }
+    // by default, repos have no config files
+    Mock::given(method("GET"))
+        .and(path_regex(format!("^/repos/{ORG}/benchmark/contents/.*")))
+        .respond_with(not_found())
+        .mount(&mock_server)
+        .await;
     if let Some(template) = template {
         let uri =
             format!("/repos/{ORG}/benchmark/commits/0d41f8596349daeadaa17c551fa0598f0a95666d");
@@ -74,23 +91,75 @@ async fn setup_github_api(template: Option<ResponseTemplate>) -> MockServer {
     mock_server
 }
 
-async fn app(template: Option<ResponseTemplate>) -> (Router, Receiver<Event>) {
+fn not_found() -> ResponseTemplate {
+    ResponseTemplate::new(404).set_body_json(serde_json::json!({
+        "documentation_url": "",
+        "message": "Not Found",
+    }))
+}
+
+/// Mock a file in the repository at `path`.
+fn file_mock(path_: &str, content: &str) -> Mock {
+    let content = base64::engine::general_purpose::STANDARD.encode(content);
+    Mock::given(method("GET"))
+        .and(path(format!("/repos/{ORG}/benchmark/contents/{path_}")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "type": "file",
+            "encoding": "base64",
+            "size": content.len(),
+            "name": path_.rsplit('/').next(),
+            "path": path_,
+            "content": content,
+            "sha": "3d21ec53a331a6f037a91c368710b99387d012c1",
+            "url": "",
+            "git_url": null,
+            "html_url": null,
+            "download_url": null,
+            "_links": { "self": "https://api.github.com/" },
+        })))
+}
+
+async fn app(template: Option<ResponseTemplate>) -> (Router, Arc<Queue>, TestTempDir) {
+    app_with_mocks(template, vec![]).await
+}
+
+async fn app_with_mocks(
+    template: Option<ResponseTemplate>,
+    mocks: Vec<Mock>,
+) -> (Router, Arc<Queue>, TestTempDir) {
     // https://github.com/flows-network/octocrab/blob/main/examples/custom_client.rs
-    let mock_github_server = setup_github_api(template).await;
-    let (sender, receiver) = channel(1);
+    let mock_github_server = setup_github_api(template, mocks).await;
+    let tmp_dir = test_temp_dir::test_temp_dir!();
+    let queue = Arc::new(Queue::open(tmp_dir.as_path_untracked().join("queue.json")).unwrap());
+    let github = Arc::new(GitHubClient::plain(
+        Octocrab::builder()
+            .base_uri(mock_github_server.uri())
+            .unwrap()
+            .build()
+            .unwrap(),
+    ));
     let state = AppState {
-        sender,
+        queue: queue.clone(),
         secret: GitHubSecret(Arc::new(TEST_SECRET.to_owned())),
-        github_client: Arc::new(
-            Octocrab::builder()
-                .base_uri(mock_github_server.uri())
-                .unwrap()
-                .build()
-                .unwrap(),
+        github_client: github.client().clone(),
+        github,
+        trust: Arc::new(TrustPolicy {
+            users: vec!["flying-sheep".to_owned()],
+            org_members: false,
+            min_permission: None,
+        }),
+        history: Arc::new(
+            History::open(&tmp_dir.as_path_untracked().join("history.sqlite")).unwrap(),
         ),
     };
-    let router = Router::new().route("/", post(handle)).with_state(state);
-    (router, receiver)
+    let sites_dir = tmp_dir.as_path_untracked().join("sites");
+    let logs_dir = tmp_dir.as_path_untracked().join("logs");
+    let charts_dir = tmp_dir.as_path_untracked().join("charts");
+    (
+        router(state, &sites_dir, &logs_dir, &charts_dir),
+        queue,
+        tmp_dir,
+    )
 }
 
 fn make_webhook_request<B: Into<Body> + AsRef<[u8]>>(body: B, valid: bool) -> Request {
@@ -109,6 +178,13 @@ fn make_webhook_request<B: Into<Body> + AsRef<[u8]>>(body: B, valid: bool) -> Re
         .unwrap()
 }
 
+fn with_event_type(mut request: Request, event_type: &'static str) -> Request {
+    request
+        .headers_mut()
+        .insert("X-GitHub-Event", HeaderValue::from_static(event_type));
+    request
+}
+
 async fn body_string(body: Body) -> String {
     String::from_utf8_lossy(&body.collect().await.unwrap().to_bytes()).into_owned()
 }
@@ -119,45 +195,57 @@ async fn assert_status_eq(res: Response<Body>, status_expected: StatusCode) -> S
     if status == status_expected {
         return body;
     }
-    panic!("{status} != {status_expected} ({body})",);
+    panic!("{status} != {status_expected} ({body})");
 }
 
 #[tokio::test]
 async fn should_error_on_invalid_signature() {
-    let (app, mut recv) = app(None).await;
+    let (app, queue, _tmp_dir) = app(None).await;
     let request = make_webhook_request(PR, false);
     let res = app.oneshot(request).await.unwrap();
 
     let body = assert_status_eq(res, StatusCode::BAD_REQUEST).await;
     assert_eq!(&body, "signature mismatch");
-    assert!(recv.next().await.is_none());
+    assert!(queue.pending().is_empty());
 }
 
 #[tokio::test]
 async fn should_error_on_invalid_event_payload() {
-    let (app, mut recv) = app(None).await;
+    let (app, queue, _tmp_dir) = app(None).await;
     let request = make_webhook_request("{}", true);
     let res = app.oneshot(request).await.unwrap();
 
     let body = assert_status_eq(res, StatusCode::BAD_REQUEST).await;
     assert!(body.starts_with("missing field"));
-    assert!(recv.next().await.is_none());
+    assert!(queue.pending().is_empty());
 }
 
 #[tokio::test]
 async fn should_skip_on_no_label() {
     let commit_after: Commit = serde_json::from_str(COMMIT).unwrap();
     let template = ResponseTemplate::new(200).set_body_json(commit_after);
-    let (app, mut recv) = app(Some(template)).await;
+    let (app, queue, _tmp_dir) = app(Some(template)).await;
     // remove the benchmark label
-    let mut evt: PullRequestWebhookEventPayload = serde_json::from_str(PR).unwrap();
-    evt.pull_request.labels.as_mut().unwrap().clear();
+    let mut evt: serde_json::Value = serde_json::from_str(PR).unwrap();
+    evt["pull_request"]["labels"] = serde_json::json!([]);
     let request = make_webhook_request(serde_json::to_string(&evt).unwrap(), true);
     let res = app.oneshot(request).await.unwrap();
 
     let body = assert_status_eq(res, StatusCode::OK).await;
     assert_eq!(&body, "skipped: missing benchmark label");
-    assert!(recv.next().await.is_none());
+    assert!(queue.pending().is_empty());
+}
+
+#[tokio::test]
+async fn should_use_configured_label() {
+    let config_mock = file_mock(".github/benchmark.toml", "label = \"perf\"");
+    let (app, queue, _tmp_dir) = app_with_mocks(None, vec![config_mock]).await;
+    let request = make_webhook_request(PR, true);
+    let res = app.oneshot(request).await.unwrap();
+
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    assert_eq!(&body, "skipped: missing perf label");
+    assert!(queue.pending().is_empty());
 }
 
 #[tokio::test]
@@ -170,17 +258,509 @@ async fn should_enqueue_valid_pr_event() {
     let commit_after: Commit = serde_json::from_str(COMMIT).unwrap();
     assert_eq!(commit_after.sha, sha_head);
     let template = ResponseTemplate::new(200).set_body_json(commit_after);
-    let (app, mut recv) = app(Some(template)).await;
-    let request = make_webhook_request(serde_json::to_string(&evt).unwrap(), true);
+    let (app, queue, _tmp_dir) = app(Some(template)).await;
+    let request = make_webhook_request(PR, true);
     let res = app.oneshot(request).await.unwrap();
 
     let body = assert_status_eq(res, StatusCode::OK).await;
     assert_eq!(body, "enqueued");
     let evt = Compare {
+        owner: ORG.to_owned(),
         repo: evt.pull_request.base.repo.unwrap().name,
+        installation: None,
         commits: [sha_base.to_owned(), sha_head.to_owned()],
         pr: evt.pull_request.number,
+        fork: None,
+        check_id: None,
+        filter: Filter::default(),
+    };
+    let events: Vec<Event> = queue.pending().into_iter().map(|job| job.event).collect();
+    assert_eq!(events, vec![evt.into()]);
+}
+
+#[tokio::test]
+async fn should_keep_installation_of_event() {
+    let commit_after: Commit = serde_json::from_str(COMMIT).unwrap();
+    let template = ResponseTemplate::new(200).set_body_json(commit_after);
+    let (app, queue, _tmp_dir) = app(Some(template)).await;
+    let mut evt: serde_json::Value = serde_json::from_str(PR).unwrap();
+    evt["installation"] = serde_json::json!({ "id": 42 });
+    let request = make_webhook_request(serde_json::to_string(&evt).unwrap(), true);
+    let res = app.oneshot(request).await.unwrap();
+
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    assert_eq!(body, "enqueued");
+    let events: Vec<Event> = queue.pending().into_iter().map(|job| job.event).collect();
+    let [event] = events.as_slice() else {
+        panic!("expected one event, got {events:?}");
+    };
+    assert_eq!(event.owner(), ORG);
+    assert_eq!(event.installation(), Some(42.into()));
+}
+
+#[tokio::test]
+async fn should_record_fork_of_pr() {
+    let commit_after: Commit = serde_json::from_str(COMMIT).unwrap();
+    let template = ResponseTemplate::new(200).set_body_json(commit_after);
+    let (app, queue, _tmp_dir) = app(Some(template)).await;
+    let mut evt: serde_json::Value = serde_json::from_str(PR).unwrap();
+    let head_repo = &mut evt["pull_request"]["head"]["repo"];
+    head_repo["id"] = 1.into();
+    head_repo["clone_url"] = "https://github.com/someone/benchmark.git".into();
+    let request = make_webhook_request(serde_json::to_string(&evt).unwrap(), true);
+    let res = app.oneshot(request).await.unwrap();
+
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    assert_eq!(body, "enqueued");
+    let events: Vec<Event> = queue.pending().into_iter().map(|job| job.event).collect();
+    let [Event::Compare(cmp)] = events.as_slice() else {
+        panic!("expected one comparison, got {events:?}");
+    };
+    let fork = Fork {
+        clone_url: "https://github.com/someone/benchmark.git".to_owned(),
+        branch: "test-asv-pr".to_owned(),
+    };
+    assert_eq!(cmp.fork, Some(fork));
+}
+
+#[tokio::test]
+async fn should_cancel_on_closed_pr() {
+    let evt: PullRequestWebhookEventPayload = serde_json::from_str(PR).unwrap();
+    let (app, queue, _tmp_dir) = app(None).await;
+    queue
+        .push(
+            Compare {
+                owner: ORG.to_owned(),
+                repo: evt.pull_request.base.repo.clone().unwrap().name,
+                installation: None,
+                commits: ["a".to_owned(), "b".to_owned()],
+                pr: evt.pull_request.number,
+                fork: None,
+                check_id: None,
+                filter: Filter::default(),
+            }
+            .into(),
+        )
+        .unwrap();
+    let mut evt: serde_json::Value = serde_json::from_str(PR).unwrap();
+    evt["action"] = "closed".into();
+    let request = make_webhook_request(serde_json::to_string(&evt).unwrap(), true);
+    let res = app.oneshot(request).await.unwrap();
+
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    assert_eq!(body, "cancelled: PR was closed");
+    assert!(queue.pending().is_empty());
+}
+
+#[tokio::test]
+async fn should_enqueue_comment_command() {
+    let evt: PullRequestWebhookEventPayload = serde_json::from_str(PR).unwrap();
+    let commit_after: Commit = serde_json::from_str(COMMIT).unwrap();
+    let template = ResponseTemplate::new(200).set_body_json(commit_after);
+    let pr_mock = Mock::given(method("GET"))
+        .and(path(format!("/repos/{ORG}/benchmark/pulls/11")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&evt.pull_request));
+    let (app, queue, _tmp_dir) = app_with_mocks(Some(template), vec![pr_mock]).await;
+    let request = with_event_type(make_webhook_request(COMMENT, true), "issue_comment");
+    let res = app.oneshot(request).await.unwrap();
+
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    assert_eq!(body, "enqueued");
+    let evt = Compare {
+        owner: ORG.to_owned(),
+        repo: "benchmark".to_owned(),
+        installation: None,
+        commits: [evt.pull_request.base.sha, evt.pull_request.head.sha],
+        pr: 11,
+        fork: None,
+        check_id: None,
+        filter: Filter {
+            envs: vec!["py3.12".to_owned()],
+            bench: Some("time_.*".to_owned()),
+        },
+    };
+    let events: Vec<Event> = queue.pending().into_iter().map(|job| job.event).collect();
+    assert_eq!(events, vec![evt.into()]);
+}
+
+#[tokio::test]
+async fn should_skip_comment_from_untrusted_user() {
+    let (app, queue, _tmp_dir) = app(None).await;
+    let mut evt: serde_json::Value = serde_json::from_str(COMMENT).unwrap();
+    evt["comment"]["user"]["login"] = "mallory".into();
+    let request = make_webhook_request(serde_json::to_string(&evt).unwrap(), true);
+    let res = app
+        .oneshot(with_event_type(request, "issue_comment"))
+        .await
+        .unwrap();
+
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    assert_eq!(body, "skipped: mallory is not trusted");
+    assert!(queue.pending().is_empty());
+}
+
+#[tokio::test]
+async fn should_skip_pr_from_untrusted_sender() {
+    let (app, queue, _tmp_dir) = app(None).await;
+    let mut evt: serde_json::Value = serde_json::from_str(PR).unwrap();
+    evt["sender"]["login"] = "mallory".into();
+    let request = make_webhook_request(serde_json::to_string(&evt).unwrap(), true);
+    let res = app.oneshot(request).await.unwrap();
+
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    assert_eq!(body, "skipped: mallory is not trusted");
+    assert!(queue.pending().is_empty());
+}
+
+#[tokio::test]
+async fn should_enqueue_push_to_default_branch() {
+    let (app, queue, _tmp_dir) = app(None).await;
+    let request = with_event_type(make_webhook_request(PUSH, true), "push");
+    let res = app.oneshot(request).await.unwrap();
+
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    assert_eq!(body, "enqueued");
+    let evt = Run {
+        owner: ORG.to_owned(),
+        repo: "benchmark".to_owned(),
+        installation: None,
+        branch: "main".to_owned(),
+        commit: "0d41f8596349daeadaa17c551fa0598f0a95666d".to_owned(),
         check_id: None,
     };
-    assert_eq!(recv.next().await, Some(evt.into()));
+    let events: Vec<Event> = queue.pending().into_iter().map(|job| job.event).collect();
+    assert_eq!(events, vec![evt.into()]);
+}
+
+#[tokio::test]
+async fn should_skip_push_to_untracked_branch() {
+    // the repo has no asv config
+    let (app, queue, _tmp_dir) = app(None).await;
+    let mut evt: serde_json::Value = serde_json::from_str(PUSH).unwrap();
+    evt["ref"] = "refs/heads/feature".into();
+    let request = make_webhook_request(serde_json::to_string(&evt).unwrap(), true);
+    let res = app.oneshot(with_event_type(request, "push")).await.unwrap();
+
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    assert_eq!(body, "skipped: branch is not benchmarked");
+    assert!(queue.pending().is_empty());
+}
+
+#[tokio::test]
+async fn should_serve_status_json() {
+    let (app, queue, _tmp_dir) = app(None).await;
+    let evt: serde_json::Value = serde_json::from_str(PUSH).unwrap();
+    let request = with_event_type(make_webhook_request(evt.to_string(), true), "push");
+    let res = app.clone().oneshot(request).await.unwrap();
+    assert_status_eq(res, StatusCode::OK).await;
+    queue.next().await.unwrap();
+
+    let request = Request::get("/api/status").body(Body::empty()).unwrap();
+    let res = app.oneshot(request).await.unwrap();
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    let status: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(status["running"]["id"], 0);
+    assert_eq!(status["running"]["event"]["Run"]["branch"], "main");
+    assert!(status["running"]["elapsed_seconds"].is_number());
+    assert_eq!(status["pending"], serde_json::json!([]));
+    assert_eq!(status["recent"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn should_render_dashboard() {
+    let (app, queue, _tmp_dir) = app(None).await;
+    let request = with_event_type(make_webhook_request(PUSH, true), "push");
+    let res = app.clone().oneshot(request).await.unwrap();
+    assert_status_eq(res, StatusCode::OK).await;
+    let (job, _) = queue.next().await.unwrap();
+    let outcome = Outcome::Errored {
+        message: "<oops>".to_owned(),
+    };
+    queue.finish(job.id, outcome).unwrap();
+    // cancelled before it started, so there’s no log
+    let (id, _) = queue.push(job.event).unwrap();
+    queue
+        .cancel(&CancelReason::Operator, |job| job.id == id)
+        .unwrap();
+
+    let request = Request::get("/").body(Body::empty()).unwrap();
+    let res = app.oneshot(request).await.unwrap();
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    assert!(body.contains("<p>Idle.</p>"), "{body}");
+    assert!(body.contains("No jobs waiting."), "{body}");
+    assert!(
+        body.contains("scverse/benchmark@main at 0d41f859"),
+        "{body}"
+    );
+    // messages are escaped
+    assert!(body.contains("Errored: &#60;oops&#62;"), "{body}");
+    assert!(body.contains(r#"<a href="logs/0.log">log</a>"#), "{body}");
+    assert!(!body.contains("logs/1.log"), "{body}");
+}
+
+#[tokio::test]
+async fn should_serve_history() {
+    let (app, _queue, tmp_dir) = app(None).await;
+    let history = History::open(&tmp_dir.as_path_untracked().join("history.sqlite")).unwrap();
+    let benchmarks = parse_benchmarks(ASV_BENCHMARKS).unwrap();
+    for json in [ASV_RESULTS_BEFORE, ASV_RESULTS_AFTER] {
+        let results = Results::parse(json).unwrap();
+        history
+            .insert(ORG, "benchmark", &results, &benchmarks)
+            .unwrap();
+    }
+
+    let uri = format!("/api/history/{ORG}/benchmark/benchmarks.TimeSuite.time_iterkeys");
+    let request = Request::get(uri).body(Body::empty()).unwrap();
+    let res = app.clone().oneshot(request).await.unwrap();
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    let points: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(points.as_array().unwrap().len(), 2);
+
+    let uri = format!("/api/compare/{ORG}/benchmark/c1a9c5db/0d41f859");
+    let request = Request::get(uri).body(Body::empty()).unwrap();
+    let res = app.clone().oneshot(request).await.unwrap();
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    assert!(body.contains("[c1a9c5db]"), "{body}");
+
+    let uri = format!("/api/compare/{ORG}/benchmark/c1a9c5db/ffffffff");
+    let request = Request::get(uri).body(Body::empty()).unwrap();
+    let res = app.clone().oneshot(request).await.unwrap();
+    assert_status_eq(res, StatusCode::NOT_FOUND).await;
+
+    // another commit starting like the first one
+    let mut results = Results::parse(ASV_RESULTS_BEFORE).unwrap();
+    results.commit_hash = "c1a9c5dbffff".to_owned();
+    history
+        .insert(ORG, "benchmark", &results, &benchmarks)
+        .unwrap();
+    let uri = format!("/api/compare/{ORG}/benchmark/c1a9c5db/0d41f859");
+    let request = Request::get(uri).body(Body::empty()).unwrap();
+    let res = app.oneshot(request).await.unwrap();
+    assert_status_eq(res, StatusCode::BAD_REQUEST).await;
+}
+
+#[tokio::test]
+async fn should_serve_sites() {
+    let (app, _queue, tmp_dir) = app(None).await;
+    let site_dir = tmp_dir
+        .as_path_untracked()
+        .join("sites")
+        .join(ORG)
+        .join("benchmark");
+    std::fs::create_dir_all(&site_dir).unwrap();
+    std::fs::write(site_dir.join("index.html"), "<h1>airspeed velocity</h1>").unwrap();
+
+    let uri = format!("/sites/{ORG}/benchmark/");
+    let request = Request::get(uri).body(Body::empty()).unwrap();
+    let res = app.clone().oneshot(request).await.unwrap();
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    assert_eq!(body, "<h1>airspeed velocity</h1>");
+
+    let request = Request::get("/sites/other/repo/")
+        .body(Body::empty())
+        .unwrap();
+    let res = app.oneshot(request).await.unwrap();
+    assert_status_eq(res, StatusCode::NOT_FOUND).await;
+}
+
+#[tokio::test]
+async fn should_serve_logs() {
+    let (app, _queue, tmp_dir) = app(None).await;
+    let logs_dir = tmp_dir.as_path_untracked().join("logs");
+    std::fs::create_dir_all(&logs_dir).unwrap();
+    std::fs::write(logs_dir.join("0.log"), "==> [run] asv run\n").unwrap();
+
+    let request = Request::get("/logs/0.log").body(Body::empty()).unwrap();
+    let res = app.clone().oneshot(request).await.unwrap();
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    assert_eq!(body, "==> [run] asv run\n");
+
+    let request = Request::get("/logs/1.log").body(Body::empty()).unwrap();
+    let res = app.oneshot(request).await.unwrap();
+    assert_status_eq(res, StatusCode::NOT_FOUND).await;
+}
+
+/// A `check_run` webhook payload for the check run of PR 11.
+fn check_run_event(action: &str, identifier: Option<&str>) -> String {
+    let pr: serde_json::Value = serde_json::from_str(PR).unwrap();
+    let mut evt = serde_json::json!({
+        "action": action,
+        "check_run": {
+            "id": 4,
+            "name": "benchmark",
+            "external_id": "11",
+            "pull_requests": [],
+        },
+        "repository": pr["repository"],
+        "sender": pr["sender"],
+    });
+    if let Some(identifier) = identifier {
+        evt["requested_action"] = serde_json::json!({ "identifier": identifier });
+    }
+    serde_json::to_string(&evt).unwrap()
+}
+
+#[tokio::test]
+async fn should_rerun_on_check_run_button() {
+    assert_rerun_on_check_run(&check_run_event("requested_action", Some("rerun"))).await;
+}
+
+#[tokio::test]
+async fn should_rerun_on_rerequested_check_run() {
+    assert_rerun_on_check_run(&check_run_event("rerequested", None)).await;
+}
+
+async fn assert_rerun_on_check_run(payload: &str) {
+    let (body, cmp) = rerun(payload, "check_run", vec![]).await;
+    assert_eq!(body, "enqueued");
+    assert_eq!(cmp.pr, 11);
+    assert_eq!(cmp.filter, Filter::default());
+}
+
+/// Send a webhook that should re-run PR 11, and get the response and the queued comparison.
+async fn rerun(payload: &str, event_type: &'static str, mut mocks: Vec<Mock>) -> (String, Compare) {
+    let evt: PullRequestWebhookEventPayload = serde_json::from_str(PR).unwrap();
+    let commit_after: Commit = serde_json::from_str(COMMIT).unwrap();
+    let template = ResponseTemplate::new(200).set_body_json(commit_after);
+    mocks.push(
+        Mock::given(method("GET"))
+            .and(path(format!("/repos/{ORG}/benchmark/pulls/11")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&evt.pull_request)),
+    );
+    let (app, queue, _tmp_dir) = app_with_mocks(Some(template), mocks).await;
+    let request = make_webhook_request(payload.to_owned(), true);
+    let res = app
+        .oneshot(with_event_type(request, event_type))
+        .await
+        .unwrap();
+
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    let events: Vec<Event> = queue.pending().into_iter().map(|job| job.event).collect();
+    let [Event::Compare(cmp)] = events.as_slice() else {
+        panic!("expected one comparison, got {events:?}");
+    };
+    (body, cmp.clone())
+}
+
+fn check_suite_event(pull_requests: &[u64]) -> String {
+    let pr: serde_json::Value = serde_json::from_str(PR).unwrap();
+    let pull_requests: Vec<_> = pull_requests
+        .iter()
+        .map(|number| serde_json::json!({ "number": number }))
+        .collect();
+    serde_json::to_string(&serde_json::json!({
+        "action": "rerequested",
+        "check_suite": { "id": 5, "pull_requests": pull_requests },
+        "repository": pr["repository"],
+        "sender": pr["sender"],
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn should_rerun_on_rerequested_check_suite() {
+    // the new check run offers to cancel the queued job
+    let check_run = serde_json::json!({
+        "id": 7,
+        "node_id": "CR_7",
+        "details_url": null,
+        "head_sha": "abc",
+        "url": "https://api.github.com/repos/scverse/benchmark/check-runs/7",
+        "html_url": null,
+        "conclusion": null,
+        "output": { "title": null, "summary": null, "text": null, "annotations_count": 0, "annotations_url": "" },
+        "started_at": null,
+        "completed_at": null,
+        "name": "benchmark",
+        "pull_requests": [],
+    });
+    let create_mock = Mock::given(method("POST"))
+        .and(path(format!("/repos/{ORG}/benchmark/check-runs")))
+        .and(body_partial_json(serde_json::json!({
+            "external_id": "11",
+            "actions": [{ "identifier": "cancel" }],
+        })))
+        .respond_with(ResponseTemplate::new(201).set_body_json(check_run));
+    let (body, cmp) = rerun(&check_suite_event(&[11]), "check_suite", vec![create_mock]).await;
+    assert_eq!(body, "#11: enqueued");
+    assert_eq!(cmp.check_id, Some(7.into()));
+}
+
+#[tokio::test]
+async fn should_rerun_on_rerequested_check_suite_of_fork() {
+    // GitHub doesn’t list PRs from forks, but the check runs’ external IDs have them
+    let runs_mock = Mock::given(method("GET"))
+        .and(path(format!(
+            "/repos/{ORG}/benchmark/check-suites/5/check-runs"
+        )))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "total_count": 1,
+            "check_runs": [{ "external_id": "11" }],
+        })));
+    let (body, cmp) = rerun(&check_suite_event(&[]), "check_suite", vec![runs_mock]).await;
+    assert_eq!(body, "#11: enqueued");
+    assert_eq!(cmp.pr, 11);
+}
+
+#[tokio::test]
+async fn should_cancel_on_check_run_action() {
+    let (app, queue, _tmp_dir) = app(None).await;
+    queue
+        .push(
+            Compare {
+                owner: ORG.to_owned(),
+                repo: "benchmark".to_owned(),
+                installation: None,
+                commits: ["a".to_owned(), "b".to_owned()],
+                pr: 11,
+                fork: None,
+                check_id: None,
+                filter: Filter::default(),
+            }
+            .into(),
+        )
+        .unwrap();
+    let request = make_webhook_request(check_run_event("requested_action", Some("cancel")), true);
+    let res = app
+        .oneshot(with_event_type(request, "check_run"))
+        .await
+        .unwrap();
+
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    assert!(body.starts_with("cancelled: Cancelled by @"), "{body}");
+    assert!(queue.pending().is_empty());
+}
+
+#[tokio::test]
+async fn should_skip_check_run_without_pr() {
+    let (app, queue, _tmp_dir) = app(None).await;
+    let mut evt: serde_json::Value =
+        serde_json::from_str(&check_run_event("requested_action", Some("rerun"))).unwrap();
+    evt["check_run"]["external_id"] = serde_json::Value::Null;
+    let request = make_webhook_request(serde_json::to_string(&evt).unwrap(), true);
+    let res = app
+        .oneshot(with_event_type(request, "check_run"))
+        .await
+        .unwrap();
+
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    assert_eq!(body, "skipped: not a PR check run");
+    assert!(queue.pending().is_empty());
+}
+
+#[tokio::test]
+async fn should_serve_charts() {
+    let (app, _queue, tmp_dir) = app(None).await;
+    let repo_dir = tmp_dir.as_path_untracked().join("charts/scverse/benchmark");
+    std::fs::create_dir_all(&repo_dir).unwrap();
+    std::fs::write(repo_dir.join("a-b.svg"), "<svg></svg>\n").unwrap();
+
+    let request = Request::get("/charts/scverse/benchmark/a-b.svg")
+        .body(Body::empty())
+        .unwrap();
+    let res = app.oneshot(request).await.unwrap();
+    assert_eq!(res.headers()["content-type"], "image/svg+xml");
+    let body = assert_status_eq(res, StatusCode::OK).await;
+    assert_eq!(body, "<svg></svg>\n");
 }
@@ -2,22 +2,23 @@ use std::sync::LazyLock;
 
 use anyhow::{Context, Result};
 use futures::{stream, StreamExt, TryStreamExt};
+use octocrab::models::repos::Content;
 use octocrab::params::repos::Reference;
 use regex::Regex;
 
-use crate::constants::ORG;
-use crate::nightly_backports::floor_char_boundary;
+use crate::nightly_backports::{ceil_char_boundary, floor_char_boundary};
 
 static SHA1_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[a-f0-9]{40}$").unwrap());
 
 pub(super) async fn ref_exists(
     github_client: &octocrab::Octocrab,
+    owner: &str,
     repo: &str,
     git_ref: &str,
 ) -> Result<bool> {
     if SHA1_RE.is_match(git_ref) {
         return Ok(github_client
-            .commits(ORG, repo)
+            .commits(owner, repo)
             .get(git_ref)
             .await
             .found()
@@ -30,7 +31,7 @@ pub(super) async fn ref_exists(
     ])
     .then(|reference| async move {
         github_client
-            .repos(ORG, repo)
+            .repos(owner, repo)
             .get_ref(&reference)
             .await
             .found()
@@ -40,6 +41,32 @@ pub(super) async fn ref_exists(
     .context("failed to check if ref exists")
 }
 
+/// Get a text file’s content at a ref, or `None` if it doesn’t exist.
+pub(super) async fn get_file(
+    github_client: &octocrab::Octocrab,
+    owner: &str,
+    repo: &str,
+    path: &str,
+    git_ref: &str,
+) -> Result<Option<String>> {
+    let Some(mut content) = github_client
+        .repos(owner, repo)
+        .get_content()
+        .path(path)
+        .r#ref(git_ref)
+        .send()
+        .await
+        .found()
+        .with_context(|| format!("failed to get {path}"))?
+    else {
+        return Ok(None);
+    };
+    Ok(content
+        .take_items()
+        .first()
+        .and_then(Content::decoded_content))
+}
+
 trait OctocrabOptional<T> {
     fn found(self) -> octocrab::Result<Option<T>>;
 }
@@ -70,6 +97,19 @@ pub(super) fn clamp_lines(text: &str, max_bytes: usize) -> &str {
     }
 }
 
+/// Like [`clamp_lines`], but keep the end of `text`.
+pub(super) fn clamp_lines_end(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let text = &text[ceil_char_boundary(text, text.len() - max_bytes)..];
+    if let Some(idx) = text.find('\n') {
+        &text[idx + 1..] // clamp it to the first line fitting the limit
+    } else {
+        text // no lines here, clamp it wherever
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -94,4 +134,13 @@ mod tests {
         assert_eq!(clamp_lines("foo xyz", 5), "foo x");
         assert_eq!(clamp_lines("老虎", 5), "老");
     }
+
+    #[test]
+    fn test_clamp_lines_end() {
+        assert_eq!(clamp_lines_end("foo\nbar\nbaz", 5), "baz");
+        assert_eq!(clamp_lines_end("foo\nbar\nbaz", 10), "bar\nbaz");
+        assert_eq!(clamp_lines_end("foo\nbar\nbaz", 15), "foo\nbar\nbaz");
+        assert_eq!(clamp_lines_end("foo xyz", 5), "o xyz");
+        assert_eq!(clamp_lines_end("老虎", 5), "虎");
+    }
 }
@@ -0,0 +1,615 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{oneshot, Notify};
+
+use crate::event::Event;
+
+/// How many finished jobs are kept for the status page.
+const RECENT_JOBS: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct JobId(u64);
+
+impl JobId {
+    /// The job’s number, e.g. for file names.
+    pub fn get(self) -> u64 {
+        self.0
+    }
+}
+
+impl Display for JobId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Parse a job number, with or without the leading `#` it’s displayed with.
+impl FromStr for JobId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.strip_prefix('#').unwrap_or(s).parse().map(Self)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Job {
+    pub id: JobId,
+    pub event: Event,
+    pub enqueued_at: DateTime<Utc>,
+}
+
+/// Why a job was removed from the queue or stopped while running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum CancelReason {
+    /// The PR was closed.
+    Closed,
+    /// The benchmark label was removed from the PR.
+    Unlabeled,
+    /// A newer commit was pushed to the PR.
+    Superseded { by: String },
+    /// A maintainer asked for it.
+    Requested { by: String },
+    /// Someone with access to the server asked for it, using `benchmark queue cancel`.
+    Operator,
+}
+
+impl Display for CancelReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Closed => write!(f, "PR was closed"),
+            Self::Unlabeled => write!(f, "Benchmark label was removed"),
+            Self::Superseded { by } => write!(f, "Superseded by {by}"),
+            Self::Requested { by } => write!(f, "Cancelled by @{by}"),
+            Self::Operator => write!(f, "Cancelled by the server’s operator"),
+        }
+    }
+}
+
+/// How a job ended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub(crate) enum Outcome {
+    /// All benchmarks ran.
+    Succeeded,
+    /// Some benchmarks failed.
+    Failed,
+    /// The job couldn’t be completed.
+    Errored { message: String },
+    /// The job was cancelled before or while running.
+    Cancelled { reason: String },
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Succeeded => write!(f, "Succeeded"),
+            Self::Failed => write!(f, "Some benchmarks failed"),
+            Self::Errored { message } => write!(f, "Errored: {message}"),
+            Self::Cancelled { reason } => write!(f, "Cancelled: {reason}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FinishedJob {
+    #[serde(flatten)]
+    pub job: Job,
+    /// When the job started running, `None` if it was cancelled before
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+/// A snapshot of the queue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Status {
+    /// If the runner waits instead of starting pending jobs
+    pub paused: bool,
+    pub running: Option<RunningJob>,
+    pub pending: Vec<Job>,
+    /// Finished jobs, most recent first
+    pub recent: Vec<FinishedJob>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct RunningJob {
+    #[serde(flatten)]
+    pub job: Job,
+    pub started_at: DateTime<Utc>,
+    pub elapsed_seconds: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    next_id: u64,
+    running: Option<Job>,
+    #[serde(default)]
+    running_since: Option<DateTime<Utc>>,
+    pending: VecDeque<Job>,
+    /// Finished jobs, most recent last
+    #[serde(default)]
+    finished: VecDeque<FinishedJob>,
+    /// Don’t start pending jobs until resumed.
+    #[serde(default)]
+    paused: bool,
+    /// Signals the runner to stop the running job.
+    #[serde(skip)]
+    cancel_running: Option<oneshot::Sender<CancelReason>>,
+}
+
+/// Job queue between listener and runner, persisted to disk on every change.
+#[derive(Debug)]
+pub(crate) struct Queue {
+    path: PathBuf,
+    state: Mutex<State>,
+    notify: Notify,
+}
+
+impl Queue {
+    /// Open the queue stored at `path`, or create an empty one.
+    ///
+    /// A job that was running when the queue was last persisted is moved back to the front.
+    pub(crate) fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut state: State = if path.is_file() {
+            let json = fs::read_to_string(&path)?;
+            serde_json::from_str(&json)
+                .with_context(|| format!("failed to read queue from {}", path.display()))?
+        } else {
+            State::default()
+        };
+        if let Some(job) = state.running.take() {
+            tracing::info!("Resuming interrupted job {}", job.id);
+            state.running_since = None;
+            state.pending.push_front(job);
+        }
+        persist(&path, &state)?;
+        Ok(Self {
+            path,
+            state: Mutex::new(state),
+            notify: Notify::new(),
+        })
+    }
+
+    /// Add an event to the end of the queue.
+    ///
+    /// If the new job [supersedes](Event::supersedes) pending jobs, it takes the place of the first
+    /// of them in the queue instead, and the superseded jobs are returned.
+    pub(crate) fn push(&self, event: Event) -> Result<(JobId, Vec<Job>)> {
+        let mut state = self.state.lock().expect("queue lock poisoned");
+        let id = JobId(state.next_id);
+        let job = Job {
+            id,
+            event,
+            enqueued_at: Utc::now(),
+        };
+        let original = (state.pending.clone(), state.finished.clone());
+        let place = state
+            .pending
+            .iter()
+            .position(|pending| job.event.supersedes(&pending.event));
+        let (superseded, kept): (VecDeque<_>, _) = std::mem::take(&mut state.pending)
+            .into_iter()
+            .partition(|pending| job.event.supersedes(&pending.event));
+        state.pending = kept;
+        let place = place.unwrap_or(state.pending.len());
+        state.pending.insert(place, job);
+        for job in &superseded {
+            let reason = format!("Superseded by job {id}");
+            state.record(job.clone(), None, Outcome::Cancelled { reason });
+        }
+        state.next_id += 1;
+        if let Err(e) = persist(&self.path, &state) {
+            (state.pending, state.finished) = original;
+            state.next_id -= 1;
+            return Err(e);
+        }
+        self.notify.notify_one();
+        Ok((id, superseded.into()))
+    }
+
+    /// Wait for the next job and mark it as running.
+    ///
+    /// While the queue is [paused](Self::set_paused), this waits until it’s resumed.
+    /// The returned receiver resolves if the job gets cancelled while running.
+    pub(crate) async fn next(&self) -> Result<(Job, oneshot::Receiver<CancelReason>)> {
+        loop {
+            // create the future before checking, so a `push` in between isn’t missed
+            let notified = self.notify.notified();
+            {
+                let mut state = self.state.lock().expect("queue lock poisoned");
+                let next = if state.paused {
+                    None
+                } else {
+                    state.pending.pop_front()
+                };
+                if let Some(job) = next {
+                    let (sender, receiver) = oneshot::channel();
+                    state.running = Some(job.clone());
+                    state.running_since = Some(Utc::now());
+                    state.cancel_running = Some(sender);
+                    if let Err(e) = persist(&self.path, &state) {
+                        state.running = None;
+                        state.running_since = None;
+                        state.cancel_running = None;
+                        state.pending.push_front(job);
+                        return Err(e);
+                    }
+                    return Ok((job, receiver));
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Mark the running job as done, and remember its outcome.
+    ///
+    /// If persisting fails, the job is still marked as done in memory.
+    pub(crate) fn finish(&self, id: JobId, outcome: Outcome) -> Result<()> {
+        let mut state = self.state.lock().expect("queue lock poisoned");
+        if state.running.as_ref().is_some_and(|job| job.id == id) {
+            let job = state.running.take().expect("checked above");
+            let started_at = state.running_since.take();
+            state.cancel_running = None;
+            state.record(job, started_at, outcome);
+        }
+        persist(&self.path, &state)
+    }
+
+    /// Remove pending jobs matching `pred`, and signal the running job to stop if it matches.
+    ///
+    /// Returns the removed pending jobs, and whether the running job matched.
+    pub(crate) fn cancel(
+        &self,
+        reason: &CancelReason,
+        pred: impl Fn(&Job) -> bool,
+    ) -> Result<(Vec<Job>, bool)> {
+        let mut state = self.state.lock().expect("queue lock poisoned");
+        let original = (state.pending.clone(), state.finished.clone());
+        let cancelled: Vec<Job> = original.0.iter().filter(|job| pred(job)).cloned().collect();
+        state.pending.retain(|job| !pred(job));
+        for job in &cancelled {
+            let reason = reason.to_string();
+            state.record(job.clone(), None, Outcome::Cancelled { reason });
+        }
+        if let Err(e) = persist(&self.path, &state) {
+            (state.pending, state.finished) = original;
+            return Err(e);
+        }
+        let running = state.running.as_ref().is_some_and(&pred);
+        if running {
+            if let Some(sender) = state.cancel_running.take() {
+                // if the runner is already done with the job, there’s nothing to stop
+                let _ = sender.send(reason.clone());
+            }
+        }
+        Ok((cancelled, running))
+    }
+
+    /// Stop starting pending jobs, or start them again.
+    ///
+    /// A running job is not affected.
+    pub(crate) fn set_paused(&self, paused: bool) -> Result<()> {
+        let mut state = self.state.lock().expect("queue lock poisoned");
+        state.paused = paused;
+        if let Err(e) = persist(&self.path, &state) {
+            state.paused = !paused;
+            return Err(e);
+        }
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Jobs waiting to be run, in order.
+    pub(crate) fn pending(&self) -> Vec<Job> {
+        let state = self.state.lock().expect("queue lock poisoned");
+        state.pending.iter().cloned().collect()
+    }
+
+    /// A snapshot of the running, pending, and recently finished jobs.
+    pub(crate) fn status(&self) -> Status {
+        let state = self.state.lock().expect("queue lock poisoned");
+        let running = state
+            .running
+            .clone()
+            .zip(state.running_since)
+            .map(|(job, started_at)| RunningJob {
+                job,
+                started_at,
+                elapsed_seconds: (Utc::now() - started_at).num_seconds(),
+            });
+        Status {
+            paused: state.paused,
+            running,
+            pending: state.pending.iter().cloned().collect(),
+            recent: state.finished.iter().rev().cloned().collect(),
+        }
+    }
+}
+
+impl State {
+    /// Remember a finished job, forgetting the oldest ones beyond [`RECENT_JOBS`].
+    fn record(&mut self, job: Job, started_at: Option<DateTime<Utc>>, outcome: Outcome) {
+        self.finished.push_back(FinishedJob {
+            job,
+            started_at,
+            finished_at: Utc::now(),
+            outcome,
+        });
+        while self.finished.len() > RECENT_JOBS {
+            self.finished.pop_front();
+        }
+    }
+}
+
+/// Write the state to a temporary file and move it into place, so it’s never half-written.
+fn persist(path: &Path, state: &State) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp_path = path.with_extension("json.tmp");
+    fs::write(&tmp_path, serde_json::to_vec(state)?)?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("failed to write queue to {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::FutureExt;
+    use rstest::rstest;
+
+    use crate::constants::ORG;
+    use crate::event::{Compare, Filter};
+
+    fn compare(pr: u64) -> Event {
+        Compare {
+            owner: ORG.to_owned(),
+            repo: "repo".to_owned(),
+            installation: None,
+            commits: ["a".to_owned(), "b".to_owned()],
+            pr,
+            fork: None,
+            check_id: None,
+            filter: Filter::default(),
+        }
+        .into()
+    }
+
+    #[tokio::test]
+    async fn test_push_next_finish() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let queue = Queue::open(tmp_dir.as_path_untracked().join("queue.json")).unwrap();
+        let (id1, _) = queue.push(compare(1)).unwrap();
+        let (id2, _) = queue.push(compare(2)).unwrap();
+        assert_ne!(id1, id2);
+
+        let (job, _) = queue.next().await.unwrap();
+        assert_eq!((job.id, job.event), (id1, compare(1)));
+        queue.finish(job.id, Outcome::Succeeded).unwrap();
+        assert_eq!(queue.pending().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let queue = Queue::open(tmp_dir.as_path_untracked().join("queue.json")).unwrap();
+        let (id1, _) = queue.push(compare(1)).unwrap();
+        let (id2, _) = queue.push(compare(2)).unwrap();
+        queue.push(compare(3)).unwrap();
+        queue.next().await.unwrap();
+        queue
+            .cancel(&CancelReason::Closed, |job| {
+                job.event.key().is_some_and(|(_, _, pr)| pr == 3)
+            })
+            .unwrap();
+
+        let status = queue.status();
+        let running = status.running.unwrap();
+        assert_eq!(running.job.id, id1);
+        assert!(running.elapsed_seconds >= 0);
+        assert_eq!(status.pending.len(), 1);
+        assert_eq!(status.recent.len(), 1);
+        let reason = "PR was closed".to_owned();
+        assert_eq!(status.recent[0].outcome, Outcome::Cancelled { reason });
+        assert_eq!(status.recent[0].started_at, None);
+
+        queue.finish(id1, Outcome::Failed).unwrap();
+        queue.next().await.unwrap();
+        let status = queue.status();
+        assert_eq!(status.running.map(|r| r.job.id), Some(id2));
+        let recent: Vec<_> = status.recent.iter().map(|f| &f.outcome).collect();
+        assert_eq!(recent[0], &Outcome::Failed);
+        assert!(status.recent[0].started_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_restore() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let path = tmp_dir.as_path_untracked().join("queue.json");
+        {
+            let queue = Queue::open(&path).unwrap();
+            queue.push(compare(1)).unwrap();
+            queue.push(compare(2)).unwrap();
+            // job 1 is interrupted while running
+            queue.next().await.unwrap();
+        }
+        let queue = Queue::open(&path).unwrap();
+        let events: Vec<_> = queue.pending().into_iter().map(|job| job.event).collect();
+        assert_eq!(events, vec![compare(1), compare(2)]);
+        // IDs keep counting up after a restore
+        let (id3, _) = queue.push(compare(3)).unwrap();
+        assert_eq!(id3, JobId(2));
+    }
+
+    #[test]
+    fn test_restore_without_owner() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let path = tmp_dir.as_path_untracked().join("queue.json");
+        // queued before events had an owner
+        let event =
+            r#"{"Compare": {"repo": "repo", "commits": ["a", "b"], "pr": 1, "check_id": null}}"#;
+        let json = format!(
+            r#"{{"next_id": 1, "running": null, "pending": [{{"id": 0, "event": {event}, "enqueued_at": "2024-01-01T00:00:00Z"}}]}}"#
+        );
+        fs::write(&path, json).unwrap();
+        let queue = Queue::open(&path).unwrap();
+        let events: Vec<_> = queue.pending().into_iter().map(|job| job.event).collect();
+        assert_eq!(events, vec![compare(1)]);
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let queue = Queue::open(tmp_dir.as_path_untracked().join("queue.json")).unwrap();
+        queue.push(compare(1)).unwrap();
+        queue.push(compare(2)).unwrap();
+        let (running, cancelled_running) = queue.next().await.unwrap();
+        assert_eq!(running.event, compare(1));
+        queue.push(compare(1)).unwrap();
+
+        let is_pr_1 = |job: &Job| matches!(&job.event, Event::Compare(cmp) if cmp.pr == 1);
+        let (cancelled, running) = queue.cancel(&CancelReason::Closed, is_pr_1).unwrap();
+        assert_eq!(cancelled.len(), 1);
+        assert!(running);
+        assert_eq!(cancelled_running.await, Ok(CancelReason::Closed));
+        let pending: Vec<_> = queue.pending().into_iter().map(|job| job.event).collect();
+        assert_eq!(pending, vec![compare(2)]);
+    }
+
+    #[tokio::test]
+    async fn test_next_persist_error() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let path = tmp_dir.as_path_untracked().join("queue.json");
+        let queue = Queue::open(&path).unwrap();
+        queue.push(compare(1)).unwrap();
+        // make writing the temporary file fail
+        let tmp_path = path.with_extension("json.tmp");
+        fs::create_dir(&tmp_path).unwrap();
+        assert!(queue.next().await.is_err());
+        // the job stays pending instead of getting lost
+        let status = queue.status();
+        assert!(status.running.is_none());
+        assert_eq!(status.pending.len(), 1);
+
+        fs::remove_dir(&tmp_path).unwrap();
+        let (job, _) = queue.next().await.unwrap();
+        assert_eq!(job.event, compare(1));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_by_id() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let queue = Queue::open(tmp_dir.as_path_untracked().join("queue.json")).unwrap();
+        queue.push(compare(1)).unwrap();
+        let (id2, _) = queue.push(compare(2)).unwrap();
+        queue.next().await.unwrap();
+        let (cancelled, running) = queue
+            .cancel(&CancelReason::Operator, |job| job.id == id2)
+            .unwrap();
+        assert!(!running);
+        assert_eq!(
+            cancelled.iter().map(|job| job.id).collect::<Vec<_>>(),
+            [id2]
+        );
+        let reason = "Cancelled by the server’s operator".to_owned();
+        assert_eq!(
+            queue.status().recent[0].outcome,
+            Outcome::Cancelled { reason }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pause() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let path = tmp_dir.as_path_untracked().join("queue.json");
+        {
+            let queue = Queue::open(&path).unwrap();
+            queue.push(compare(1)).unwrap();
+            queue.set_paused(true).unwrap();
+        }
+        // stays paused after a restart
+        let queue = Queue::open(&path).unwrap();
+        assert!(queue.status().paused);
+        assert!(queue.next().now_or_never().is_none());
+        assert_eq!(queue.pending().len(), 1);
+
+        queue.set_paused(false).unwrap();
+        let (job, _) = queue.next().now_or_never().unwrap().unwrap();
+        assert_eq!(job.event, compare(1));
+    }
+
+    #[rstest]
+    #[case("3", Ok(JobId(3)))]
+    #[case("#3", Ok(JobId(3)))]
+    #[case("three", Err(()))]
+    fn test_job_id_from_str(#[case] input: &str, #[case] expected: Result<JobId, ()>) {
+        assert_eq!(input.parse::<JobId>().map_err(|_| ()), expected);
+    }
+
+    #[tokio::test]
+    async fn test_coalesce() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let queue = Queue::open(tmp_dir.as_path_untracked().join("queue.json")).unwrap();
+        let (id1, _) = queue.push(compare(1)).unwrap();
+        queue.push(compare(2)).unwrap();
+        let mut newer = compare(1);
+        if let Event::Compare(cmp) = &mut newer {
+            cmp.commits[1] = "c".to_owned();
+        }
+        let (id3, superseded) = queue.push(newer.clone()).unwrap();
+        let ids: Vec<_> = superseded.into_iter().map(|job| job.id).collect();
+        assert_eq!(ids, vec![id1]);
+        // the same PR number in a fork’s repository is a different PR
+        let mut fork = compare(1);
+        if let Event::Compare(cmp) = &mut fork {
+            "someone".clone_into(&mut cmp.owner);
+        }
+        let (id4, superseded) = queue.push(fork).unwrap();
+        assert_eq!(superseded, vec![]);
+        // the newer job takes the place of the superseded one
+        let pending: Vec<_> = queue.pending().into_iter().map(|job| job.id).collect();
+        assert_eq!(pending, vec![id3, JobId(1), id4]);
+        assert_eq!(queue.next().await.unwrap().0.event, newer);
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_filtered() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let queue = Queue::open(tmp_dir.as_path_untracked().join("queue.json")).unwrap();
+        let mut filtered = compare(1);
+        if let Event::Compare(cmp) = &mut filtered {
+            cmp.filter.envs = vec!["py3.12".to_owned()];
+            cmp.filter.bench = Some("time_.*".to_owned());
+        }
+        let (id1, _) = queue.push(filtered.clone()).unwrap();
+        // a full run for the same head doesn’t replace the filtered one, or the reverse
+        let (id2, superseded) = queue.push(compare(1)).unwrap();
+        assert_eq!(superseded, vec![]);
+        let (id3, superseded) = queue.push(filtered).unwrap();
+        let ids: Vec<_> = superseded.into_iter().map(|job| job.id).collect();
+        assert_eq!(ids, vec![id1]);
+        // a new head supersedes both
+        let mut newer = compare(1);
+        if let Event::Compare(cmp) = &mut newer {
+            cmp.commits[1] = "c".to_owned();
+        }
+        let (id4, superseded) = queue.push(newer).unwrap();
+        let ids: Vec<_> = superseded.into_iter().map(|job| job.id).collect();
+        assert_eq!(ids, vec![id3, id2]);
+        let pending: Vec<_> = queue.pending().into_iter().map(|job| job.id).collect();
+        assert_eq!(pending, vec![id4]);
+    }
+}
@@ -1,53 +1,344 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
 use anyhow::Result;
-use futures::{channel::mpsc::Receiver, StreamExt};
+use octocrab::{params::checks::CheckRunOutputImage, Octocrab};
+
+use crate::benchmark::chart;
+use crate::benchmark::comparison::Comparison;
+use crate::benchmark::process::JobLog;
+use crate::benchmark::{publish, site_dir, sync_repo_and_run, RunResult};
+use crate::dirs::DATA_DIR;
+use crate::event::{Compare, Event, Run};
+use crate::history::History;
+use crate::octocrab_utils::GitHubClient;
 
-use crate::benchmark::{sync_repo_and_run, AsvCompare, RunResult};
-use crate::constants::ORG;
-use crate::event::{Compare, Event};
+use super::queue::{CancelReason, JobId, Outcome, Queue};
+pub(super) use checks::create_queued as create_check_run;
+use checks::{Check, Report};
 
+mod annotations;
 mod checks;
 mod comment;
 
-pub(crate) async fn runner(mut receiver: Receiver<Event>) {
-    // loop runs until sender disconnects
-    while let Some(event) = receiver.next().await {
-        if let Err(error) = handle_event(event).await {
-            tracing::error!("Handle event error: {error:?}");
+/// How long to wait before trying again when the queue can’t be persisted, e.g. because the disk is full.
+const PERSIST_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Run queued jobs one after the other, recording their results in `history`.
+///
+/// `public_url` is where the server is reachable, to link to published results.
+/// Failing to persist the queue is logged, but doesn’t stop the runner.
+pub(crate) async fn runner(
+    queue: Arc<Queue>,
+    github: Arc<GitHubClient>,
+    history: Arc<History>,
+    public_url: Option<String>,
+) -> Result<()> {
+    loop {
+        let (job, cancelled) = match queue.next().await {
+            Ok(next) => next,
+            Err(error) => {
+                tracing::error!("Failed to start next job: {error:?}");
+                tokio::time::sleep(PERSIST_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        let outcome = tokio::select! {
+            res = handle_event(&github, &history, job.event.clone(), job.id, public_url.as_deref()) => match res {
+                Ok(true) => Outcome::Succeeded,
+                Ok(false) => Outcome::Failed,
+                Err(error) => {
+                    tracing::error!("Handle event error: {error:?}");
+                    Outcome::Errored { message: format!("{error:#}") }
+                }
+            },
+            // dropping the `handle_event` future kills running `asv` processes
+            Ok(reason) = cancelled => {
+                tracing::info!("Cancelled job {}: {reason}", job.id);
+                if let Err(error) = conclude_cancelled(&github, &job.event, &reason).await {
+                    tracing::error!("Conclude cancelled error: {error:?}");
+                }
+                Outcome::Cancelled { reason: reason.to_string() }
+            }
+        };
+        if let Err(error) = queue.finish(job.id, outcome) {
+            tracing::error!("Failed to persist outcome of job {}: {error:?}", job.id);
         }
     }
 }
 
-#[tracing::instrument(skip(event))]
-async fn handle_event(event: Event) -> Result<()> {
-    match event {
+/// Where the output of jobs is stored, served under `/logs/`.
+pub(super) fn logs_dir() -> PathBuf {
+    DATA_DIR.join("logs")
+}
+
+/// How many of the most recent jobs’ logs are kept in [`logs_dir`].
+const KEPT_LOGS: u64 = 500;
+
+/// Delete the logs of jobs more than [`KEPT_LOGS`] jobs older than `job`.
+fn prune_logs(dir: &Path, job: JobId) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "log") {
+            continue;
+        }
+        let Some(id) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str()?.parse::<u64>().ok())
+        else {
+            continue;
+        };
+        if id + KEPT_LOGS <= job.get() {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Where charts of comparisons are stored, served under `/charts/`.
+pub(super) fn charts_dir() -> PathBuf {
+    DATA_DIR.join("charts")
+}
+
+/// Run the job for an event, returning if all benchmarks succeeded.
+#[tracing::instrument(skip(github, history, event, public_url), fields(%event))]
+async fn handle_event(
+    github: &GitHubClient,
+    history: &Arc<History>,
+    event: Event,
+    job: JobId,
+    public_url: Option<&str>,
+) -> Result<bool> {
+    let github_client = github_client(github, &event).await?;
+    let log = JobLog::create(logs_dir().join(format!("{}.log", job.get())))?;
+    if let Err(e) = prune_logs(&logs_dir(), job) {
+        tracing::warn!("Failed to prune old logs: {e:?}");
+    }
+    let log_url =
+        public_url.map(|url| format!("{}/logs/{}.log", url.trim_end_matches('/'), job.get()));
+    let report = match event {
         Event::Compare(ref cmp) => {
             tracing::info!("Comparing {:?} for PR {}", cmp.commits, cmp.pr);
-            let github_client = octocrab::instance();
-            let checks_handler = github_client.checks(ORG, &cmp.repo);
-            if let Some(check_id) = cmp.check_id {
-                checks::with_check(checks_handler, check_id, || full_compare(cmp)).await?;
+            if let Some(id) = cmp.check_id {
+                let check = Check {
+                    github: &github_client,
+                    owner: &cmp.owner,
+                    repo: &cmp.repo,
+                    id,
+                    actions: true,
+                };
+                checks::with_check(&check, &log, log_url.as_deref(), || {
+                    full_compare(&github_client, cmp, history, &log, public_url)
+                })
+                .await?
             } else {
-                full_compare(cmp).await?;
+                full_compare(&github_client, cmp, history, &log, public_url).await?
             }
         }
+        Event::Run(ref run) => {
+            tracing::info!("Running {} on {}", run.commit, run.branch);
+            if let Some(id) = run.check_id {
+                let check = Check {
+                    github: &github_client,
+                    owner: &run.owner,
+                    repo: &run.repo,
+                    id,
+                    actions: false,
+                };
+                checks::with_check(&check, &log, log_url.as_deref(), || {
+                    full_run(run, history, &log)
+                })
+                .await?
+            } else {
+                full_run(run, history, &log).await?
+            }
+        }
+    };
+    Ok(report.success)
+}
+
+/// Get a client acting as the app installation for the event’s repository.
+async fn github_client(github: &GitHubClient, event: &Event) -> Result<Arc<Octocrab>> {
+    github
+        .installation(event.owner(), event.repo(), event.installation())
+        .await
+}
+
+async fn full_run(run: &Run, history: &Arc<History>, log: &JobLog) -> Result<Report> {
+    let rr = sync_repo_and_run(run, history, Some(log)).await?;
+    publish_site(&run.owner, &run.repo, &rr, log).await;
+    let text = format!(
+        "Added results for {} on `{}` to the history, in environments: {}",
+        run.commit,
+        run.branch,
+        rr.env_specs.0.join(", ")
+    );
+    Ok(Report {
+        text,
+        success: rr.success,
+        ..Report::default()
+    })
+}
+
+/// Conclude the check run of a cancelled event.
+pub(super) async fn conclude_cancelled(
+    github: &GitHubClient,
+    event: &Event,
+    reason: &CancelReason,
+) -> Result<()> {
+    if let Some(id) = event.check_id() {
+        let github_client = github_client(github, event).await?;
+        let check = Check {
+            github: &github_client,
+            owner: event.owner(),
+            repo: event.repo(),
+            id,
+            actions: matches!(event, Event::Compare(_)),
+        };
+        checks::cancel(&check, reason).await?;
+    }
+    Ok(())
+}
+
+/// Give an event that is queued again a fresh check run, as its old one is already completed.
+pub(super) async fn requeue_check_run(github: &GitHubClient, event: &mut Event) -> Result<()> {
+    if event.check_id().is_none() {
+        return Ok(());
+    }
+    let github_client = github_client(github, event).await?;
+    let (head_sha, pr) = match &*event {
+        Event::Compare(cmp) => (&cmp.commits[1], Some(cmp.pr)),
+        Event::Run(run) => (&run.commit, None),
+    };
+    let id = create_check_run(&github_client, event.owner(), event.repo(), head_sha, pr).await?;
+    match event {
+        Event::Compare(cmp) => cmp.check_id = Some(id),
+        Event::Run(run) => run.check_id = Some(id),
     }
     Ok(())
 }
 
-async fn full_compare(cmp: &Compare) -> Result<(String, bool), anyhow::Error> {
-    let rr = sync_repo_and_run(cmp).await?;
-    let success = rr.success;
-    let output = compare(rr, cmp).await?;
-    Ok((output, success))
+/// Publish the results as HTML site, served under `/sites/`.
+///
+/// Failing to do so doesn’t fail the job.
+async fn publish_site(owner: &str, repo: &str, rr: &RunResult, log: &JobLog) {
+    if let Err(e) = publish(rr, &site_dir(owner, repo), Some(log)).await {
+        tracing::warn!("Failed to publish results for {owner}/{repo}: {e:?}");
+    }
 }
 
-async fn compare(rr: RunResult, cmp: &Compare) -> Result<String> {
-    let mut compare = AsvCompare::new(&rr.wd, &cmp.commits[0], &cmp.commits[1]);
-    compare.in_envs(rr.env_specs);
-    // Try updating comment with short comparison
-    if let Err(e) = comment::update(cmp, &compare.output().await?, rr.success).await {
+async fn full_compare(
+    github_client: &Octocrab,
+    cmp: &Compare,
+    history: &Arc<History>,
+    log: &JobLog,
+    public_url: Option<&str>,
+) -> Result<Report> {
+    let rr = sync_repo_and_run(cmp, history, Some(log)).await?;
+    publish_site(&cmp.owner, &cmp.repo, &rr, log).await;
+    compare(github_client, rr, cmp, public_url).await
+}
+
+async fn compare(
+    github_client: &Octocrab,
+    rr: RunResult,
+    cmp: &Compare,
+    public_url: Option<&str>,
+) -> Result<Report> {
+    let comparison = Comparison::load(
+        &rr.results_dir,
+        &cmp.commits[0],
+        &cmp.commits[1],
+        &rr.env_specs.0,
+        &rr.thresholds,
+    )?;
+    let public_url = public_url.map(|url| url.trim_end_matches('/'));
+    let site_url = public_url.map(|url| format!("{url}/sites/{}/{}/", cmp.owner, cmp.repo));
+    let chart_url = match write_chart(cmp, &comparison) {
+        Ok(path) => public_url
+            .zip(path)
+            .map(|(url, path)| format!("{url}/charts/{path}")),
+        Err(e) => {
+            tracing::warn!("Failed to write chart: {e:?}");
+            None
+        }
+    };
+    // Try updating comment with a summary of the comparison
+    if let Err(e) = comment::update(
+        github_client,
+        cmp,
+        &comparison,
+        rr.success,
+        site_url.as_deref(),
+        chart_url.as_deref(),
+        rr.comments,
+    )
+    .await
+    {
         tracing::error!("Update comment error: {e:?}");
     }
+    if let Err(e) = checks::report_envs(github_client, cmp, &comparison, &rr.env_specs.0).await {
+        tracing::error!("Failed to report environments: {e:?}");
+    }
+    let annotations = annotations::regressions(&comparison, &rr, cmp).unwrap_or_else(|e| {
+        tracing::warn!("Failed to annotate regressions: {e:?}");
+        vec![]
+    });
+    let images = chart_url
+        .into_iter()
+        .map(|image_url| CheckRunOutputImage {
+            image_url,
+            alt: "Benchmark changes".to_owned(),
+            caption: Some("Before vs after, relative to before".to_owned()),
+        })
+        .collect();
     // Return full comparison
-    compare.only_changed(false).output().await
+    Ok(Report {
+        text: comparison.markdown(false),
+        success: rr.success,
+        annotations,
+        images,
+    })
+}
+
+/// Store the comparison’s chart, if anything changed, in [`charts_dir`].
+///
+/// Returns its path relative to it, which is stable for a pair of commits.
+fn write_chart(cmp: &Compare, comparison: &Comparison) -> Result<Option<String>> {
+    let Some(svg) = chart::svg(comparison) else {
+        return Ok(None);
+    };
+    let path = format!(
+        "{}/{}/{}-{}.svg",
+        cmp.owner, cmp.repo, cmp.commits[0], cmp.commits[1]
+    );
+    let file = charts_dir().join(&path);
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(file, svg)?;
+    Ok(Some(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prune_logs() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let dir = tmp_dir.as_path_untracked();
+        for name in ["0.log", "100.log", "599.log", "600.log", "notes.txt"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        prune_logs(dir, "600".parse().unwrap()).unwrap();
+        let mut names: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["599.log", "600.log", "notes.txt"]);
+    }
 }
@@ -0,0 +1,114 @@
+//! Check run annotations pointing at the source of regressed benchmarks.
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use octocrab::params::checks::{CheckRunOutputAnnotation, CheckRunOutputAnnotationLevel};
+
+use crate::benchmark::comparison::{Change, Comparison, Row};
+use crate::benchmark::source::{locate, Location};
+use crate::benchmark::RunResult;
+use crate::event::Compare;
+
+/// GitHub accepts at most this many annotations per request
+const MAX_ANNOTATIONS: usize = 50;
+
+/// Annotate the definitions of benchmarks that got significantly slower or started failing.
+///
+/// The definitions are looked up in the PR’s head commit.
+pub(super) fn regressions(
+    comparison: &Comparison,
+    rr: &RunResult,
+    cmp: &Compare,
+) -> Result<Vec<CheckRunOutputAnnotation>> {
+    let repo = git2::Repository::discover(&rr.wd)?;
+    let root = repo.workdir().context("no workdir")?;
+    let benchmark_dir = rr
+        .benchmark_dir
+        .strip_prefix(root)
+        .context("benchmark directory is outside of the repository")?;
+    let head = &cmp.commits[1];
+    Ok(annotate(comparison, |name| {
+        locate(&repo, head, benchmark_dir, name)
+    }))
+}
+
+/// Create annotations for regressed benchmarks that `locate` finds, one per benchmark.
+fn annotate(
+    comparison: &Comparison,
+    locate: impl Fn(&str) -> Option<Location>,
+) -> Vec<CheckRunOutputAnnotation> {
+    let mut by_benchmark: BTreeMap<&str, Vec<&Row>> = BTreeMap::new();
+    for row in &comparison.rows {
+        if matches!(row.change, Change::Worse | Change::Failed) {
+            by_benchmark.entry(&row.benchmark).or_default().push(row);
+        }
+    }
+    by_benchmark
+        .into_iter()
+        .filter_map(|(name, rows)| {
+            let location = locate(name)?;
+            let message = rows
+                .iter()
+                .map(|row| format!("{}: {}", row.change.title(), row.describe()))
+                .collect::<Vec<_>>()
+                .join("\n");
+            Some(CheckRunOutputAnnotation {
+                path: location.path,
+                start_line: location.line,
+                end_line: location.line,
+                start_column: None,
+                end_column: None,
+                annotation_level: CheckRunOutputAnnotationLevel::Warning,
+                message,
+                title: Some(format!("Benchmark {} regressed", short_name(name))),
+                raw_details: None,
+            })
+        })
+        .take(MAX_ANNOTATIONS)
+        .collect()
+}
+
+fn short_name(name: &str) -> &str {
+    name.strip_prefix("benchmarks.").unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixtures::fixture_comparison;
+
+    #[test]
+    fn test_annotate() {
+        let comparison = fixture_comparison();
+        let regressed: Vec<&str> = comparison
+            .rows
+            .iter()
+            .filter(|row| matches!(row.change, Change::Worse | Change::Failed))
+            .map(|row| row.benchmark.as_str())
+            .collect();
+        assert!(!regressed.is_empty());
+
+        let annotations = annotate(&comparison, |name| {
+            (name == regressed[0]).then(|| Location {
+                path: "benchmarks/benchmarks.py".to_owned(),
+                line: 7,
+            })
+        });
+        let [annotation] = annotations.as_slice() else {
+            panic!("expected one annotation, got {}", annotations.len());
+        };
+        assert_eq!(annotation.path, "benchmarks/benchmarks.py");
+        assert_eq!((annotation.start_line, annotation.end_line), (7, 7));
+        assert!(matches!(
+            annotation.annotation_level,
+            CheckRunOutputAnnotationLevel::Warning
+        ));
+        let message = &annotation.message;
+        assert_eq!(
+            message.lines().count(),
+            regressed.iter().filter(|&&b| b == regressed[0]).count()
+        );
+        assert!(message.contains(" → "), "{message}");
+    }
+}
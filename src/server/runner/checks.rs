@@ -1,29 +1,157 @@
+use std::fmt::Write;
 use std::future::Future;
+use std::time::Duration;
 
 use anyhow::Result;
 use octocrab::{
     checks::ChecksHandler,
-    models::CheckRunId,
-    params::checks::{CheckRunConclusion, CheckRunOutput, CheckRunStatus},
+    models::{checks::CheckRun, CheckRunId},
+    params::checks::{
+        CheckRunConclusion, CheckRunOutput, CheckRunOutputAnnotation, CheckRunOutputImage,
+        CheckRunStatus,
+    },
+    Octocrab,
 };
+use serde::Serialize;
 
-use crate::server::octocrab_utils::clamp_lines;
+use crate::benchmark::comparison::{Change, Comparison};
+use crate::benchmark::process::{JobLog, TimedOut};
+use crate::benchmark::progress::Progress;
+use crate::constants::{CANCEL_ACTION, RERUN_ACTION};
+use crate::event::Compare;
+use crate::repo_cache::MissingCommit;
+use crate::server::octocrab_utils::{clamp_lines, clamp_lines_end};
+use crate::server::queue::CancelReason;
+use crate::utils::format_duration;
 
-/// Update the check run before and after the function ran.
-#[tracing::instrument(skip(checks, func))]
+/// How many lines of the job’s log are shown in the check run
+const LOG_TAIL_LINES: usize = 100;
+/// The log’s tail is clamped to this many bytes, as lines can be arbitrarily long
+const LOG_TAIL_BYTES: usize = 16 * 1024;
+/// How much of the log’s end is read, more than shown so whole lines remain after clamping
+const LOG_READ_BYTES: u64 = 2 * LOG_TAIL_BYTES as u64;
+
+/// Minimum time between progress updates of a check run, to go easy on GitHub’s API
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A button on a check run, see <https://docs.github.com/en/rest/checks/runs#check-runs-and-requested-actions>
+#[derive(Debug, Serialize)]
+struct Action {
+    /// Button text, at most 20 characters
+    label: &'static str,
+    /// Tooltip, at most 40 characters
+    description: &'static str,
+    /// Sent back in the `check_run.requested_action` webhook, at most 20 characters
+    identifier: &'static str,
+}
+
+const CANCEL: Action = Action {
+    label: "Cancel",
+    description: "Stop running the benchmarks",
+    identifier: CANCEL_ACTION,
+};
+
+const RERUN: Action = Action {
+    label: "Re-run benchmarks",
+    description: "Run the benchmarks again",
+    identifier: RERUN_ACTION,
+};
+
+/// The check run a job reports in.
+pub(super) struct Check<'a> {
+    pub github: &'a Octocrab,
+    pub owner: &'a str,
+    pub repo: &'a str,
+    pub id: CheckRunId,
+    /// Whether to offer re-running and cancelling, which the listener handles for PRs only
+    pub actions: bool,
+}
+
+impl Check<'_> {
+    fn handler(&self) -> ChecksHandler<'_> {
+        self.github.checks(self.owner, self.repo)
+    }
+
+    /// Replace the check run’s buttons, if it offers any.
+    ///
+    /// Failing to do so is only logged, the buttons are a convenience.
+    async fn set_actions(&self, actions: &[Action]) {
+        if !self.actions {
+            return;
+        }
+        let route = format!("/repos/{}/{}/check-runs/{}", self.owner, self.repo, self.id);
+        let body = serde_json::json!({ "actions": actions });
+        if let Err(e) = self
+            .github
+            .patch::<serde_json::Value, _, _>(route, Some(&body))
+            .await
+        {
+            tracing::warn!("Failed to set check run actions: {e:?}");
+        }
+    }
+}
+
+/// Create a queued check run for a job.
+///
+/// The `pr` number is stored as the check run’s external ID,
+/// since GitHub doesn’t list PRs from forks in `check_run` webhooks.
+/// PR check runs offer to cancel the job right away, while it waits in the queue.
+/// octocrab’s builder can’t set actions, so this creates the check run with a raw request.
+pub(in crate::server) async fn create_queued(
+    github: &Octocrab,
+    owner: &str,
+    repo: &str,
+    head_sha: &str,
+    pr: Option<u64>,
+) -> Result<CheckRunId> {
+    let mut body = serde_json::json!({
+        "name": "benchmark",
+        "head_sha": head_sha,
+        "status": "queued",
+    });
+    if let Some(pr) = pr {
+        body["external_id"] = pr.to_string().into();
+        body["actions"] = serde_json::json!([CANCEL]);
+    }
+    let route = format!("/repos/{owner}/{repo}/check-runs");
+    let check_run: CheckRun = github.post(route, Some(&body)).await?;
+    Ok(check_run.id)
+}
+
+/// What a job reports in its check run.
+#[derive(Default)]
+pub(super) struct Report {
+    /// Markdown shown as the check run’s text
+    pub text: String,
+    /// Whether all benchmarks succeeded
+    pub success: bool,
+    /// Annotations pointing at the source of benchmarks, see [`super::annotations`]
+    pub annotations: Vec<CheckRunOutputAnnotation>,
+    /// Charts of the results, see [`crate::benchmark::chart`]
+    pub images: Vec<CheckRunOutputImage>,
+}
+
+/// Update the check run before and after the function ran, and with its progress while it runs.
+///
+/// The end of the job’s `log` is added to the check run’s text, linking to `log_url`.
+/// While the job runs, the check run offers to cancel it, afterwards to re-run it.
+#[tracing::instrument(skip(check, log, func), fields(check_id = %check.id))]
 pub(super) async fn with_check<Fut>(
-    checks: ChecksHandler<'_>,
-    check_id: CheckRunId,
+    check: &Check<'_>,
+    log: &JobLog,
+    log_url: Option<&str>,
     func: impl Fn() -> Fut,
-) -> Result<String>
+) -> Result<Report>
 where
-    Fut: Future<Output = Result<(String, bool)>>,
+    Fut: Future<Output = Result<Report>>,
 {
+    let (checks, check_id) = (check.handler(), check.id);
     checks
         .update_check_run(check_id)
         .status(CheckRunStatus::InProgress)
         .send()
         .await?;
+    check.set_actions(&[CANCEL]).await;
     let mut output = CheckRunOutput {
         title: "Benchmark".to_owned(),
         summary: String::new(),
@@ -31,23 +159,63 @@ where
         annotations: vec![],
         images: vec![],
     };
-    let (conclusion, res) = match func().await {
-        Ok((text, success)) => {
+    let result = {
+        let func = func();
+        tokio::pin!(func);
+        let mut progress = log.progress();
+        loop {
+            tokio::select! {
+                res = &mut func => break res,
+                () = tokio::time::sleep(PROGRESS_INTERVAL) => {
+                    if !progress.has_changed().unwrap_or(false) {
+                        continue;
+                    }
+                    let Some(current) = progress.borrow_and_update().clone() else {
+                        continue;
+                    };
+                    if let Err(e) = report_progress(&checks, check_id, &current, log_url).await {
+                        tracing::warn!("Failed to report progress: {e:?}");
+                    }
+                }
+            }
+        }
+    };
+    let (conclusion, res) = match result {
+        Ok(mut report) => {
             "Benchmark run successful".clone_into(&mut output.summary);
-            output.text = Some(clamp_lines(&text, u16::MAX.into()).to_owned());
-            let conclusion = if success {
+            output.text = Some(report.text.clone());
+            output.annotations = std::mem::take(&mut report.annotations);
+            output.images = std::mem::take(&mut report.images);
+            let conclusion = if report.success {
                 CheckRunConclusion::Success
             } else {
                 CheckRunConclusion::Failure
             };
-            (conclusion, Ok(text))
+            (conclusion, Ok(report))
+        }
+        // the PR was force-pushed, its new head gets benchmarked by another job
+        Err(e) if e.downcast_ref::<MissingCommit>().is_some() => {
+            output.summary = e.to_string();
+            (CheckRunConclusion::Skipped, Err(e))
+        }
+        Err(e) if e.downcast_ref::<TimedOut>().is_some() => {
+            output.summary = e.to_string();
+            (CheckRunConclusion::TimedOut, Err(e))
         }
         Err(e) => {
             "Benchmark run failed".clone_into(&mut output.summary);
-            output.text = Some(format!("## Error message\n{e}"));
+            output.text = Some(format!("## Error message\n{e:#}"));
             (CheckRunConclusion::Failure, Err(e))
         }
     };
+    let log_section = log_section(log, log_url);
+    output.text = Some(match output.text {
+        Some(text) => {
+            let max = usize::from(u16::MAX).saturating_sub(log_section.len() + 2);
+            format!("{}\n\n{log_section}", clamp_lines(&text, max))
+        }
+        None => log_section,
+    });
     checks
         .update_check_run(check_id)
         .status(CheckRunStatus::Completed)
@@ -55,5 +223,237 @@ where
         .output(output)
         .send()
         .await?;
+    check.set_actions(&[RERUN]).await;
     res
 }
+
+/// Show how far the benchmarks got in the check run.
+async fn report_progress(
+    checks: &ChecksHandler<'_>,
+    check_id: CheckRunId,
+    progress: &Progress,
+    log_url: Option<&str>,
+) -> Result<()> {
+    let mut text = String::new();
+    if let Some(benchmark) = &progress.benchmark {
+        let _ = writeln!(text, "Current benchmark: `{benchmark}`");
+    }
+    if let Some(log_url) = log_url {
+        let _ = writeln!(text, "\n[Log so far]({log_url})");
+    }
+    checks
+        .update_check_run(check_id)
+        .output(CheckRunOutput {
+            title: "Benchmark".to_owned(),
+            summary: progress_summary(progress),
+            text: (!text.is_empty()).then_some(text),
+            annotations: vec![],
+            images: vec![],
+        })
+        .send()
+        .await?;
+    Ok(())
+}
+
+/// Summarize progress as e.g. `42% done, about 12m 3s left`.
+fn progress_summary(progress: &Progress) -> String {
+    let mut summary = format!("{:.0}% done", progress.percent);
+    if let Some(eta) = progress.eta() {
+        let seconds = i64::try_from(eta.as_secs()).unwrap_or(i64::MAX);
+        let _ = write!(summary, ", about {} left", format_duration(seconds));
+    }
+    summary
+}
+
+/// Format the end of the job’s log as Markdown.
+fn log_section(log: &JobLog, log_url: Option<&str>) -> String {
+    let tail = log
+        .tail(LOG_TAIL_LINES, LOG_READ_BYTES)
+        .unwrap_or_else(|e| {
+            tracing::warn!("Failed to read {}: {e}", log.path().display());
+            String::new()
+        });
+    // make sure the output can’t end the code block
+    let tail = tail.replace("```", "` ` `");
+    let tail = clamp_lines_end(&tail, LOG_TAIL_BYTES);
+    let link = log_url.map_or_else(String::new, |url| format!(" ([full log]({url}))"));
+    format!("## Log{link}\n\n```text\n{tail}\n```")
+}
+
+/// Report each environment’s comparison in a completed check run of its own,
+/// e.g. `benchmark (py3.12-numpy2)`, if more than one environment was run.
+pub(super) async fn report_envs(
+    github: &Octocrab,
+    cmp: &Compare,
+    comparison: &Comparison,
+    envs: &[String],
+) -> Result<()> {
+    if envs.len() < 2 {
+        return Ok(());
+    }
+    let checks = github.checks(&cmp.owner, &cmp.repo);
+    for env in envs {
+        let env_comparison = comparison.for_env(env);
+        let (conclusion, summary) = env_conclusion(&env_comparison);
+        let text = env_comparison.markdown(false);
+        checks
+            .create_check_run(format!("benchmark ({})", env_label(env)), &cmp.commits[1])
+            // lets GitHub’s “Re-run” button find the PR, see `handle_check_run`
+            .external_id(cmp.pr.to_string())
+            .status(CheckRunStatus::Completed)
+            .conclusion(conclusion)
+            .output(CheckRunOutput {
+                title: format!("Benchmark in {env}"),
+                summary,
+                text: (!text.is_empty())
+                    .then(|| clamp_lines(&text, usize::from(u16::MAX)).to_owned()),
+                annotations: vec![],
+                images: vec![],
+            })
+            .send()
+            .await?;
+    }
+    Ok(())
+}
+
+/// Conclude an environment’s check run by the most important change in it.
+///
+/// Newly failing benchmarks fail it, regressions make it neutral.
+fn env_conclusion(comparison: &Comparison) -> (CheckRunConclusion, String) {
+    if comparison.rows.is_empty() {
+        return (
+            CheckRunConclusion::Neutral,
+            "No results in this environment".to_owned(),
+        );
+    }
+    let count = |change| {
+        comparison
+            .rows
+            .iter()
+            .filter(|r| r.change == change)
+            .count()
+    };
+    let (failed, worse, better) = (
+        count(Change::Failed),
+        count(Change::Worse),
+        count(Change::Better),
+    );
+    let conclusion = if failed > 0 {
+        CheckRunConclusion::Failure
+    } else if worse > 0 {
+        CheckRunConclusion::Neutral
+    } else {
+        CheckRunConclusion::Success
+    };
+    let summary = format!("{failed} newly failing, {worse} slower, {better} faster");
+    (conclusion, summary)
+}
+
+/// Leave out the environment type, e.g. `py3.12-numpy2` for `virtualenv-py3.12-numpy2`.
+pub(super) fn env_label(env: &str) -> &str {
+    env.split_once('-').map_or(env, |(_, rest)| rest)
+}
+
+/// Conclude a check run whose job was cancelled before it finished.
+#[tracing::instrument(skip(check), fields(check_id = %check.id))]
+pub(super) async fn cancel(check: &Check<'_>, reason: &CancelReason) -> Result<()> {
+    let conclusion = match reason {
+        CancelReason::Closed
+        | CancelReason::Unlabeled
+        | CancelReason::Requested { .. }
+        | CancelReason::Operator => CheckRunConclusion::Cancelled,
+        CancelReason::Superseded { .. } => CheckRunConclusion::Skipped,
+    };
+    check
+        .handler()
+        .update_check_run(check.id)
+        .status(CheckRunStatus::Completed)
+        .conclusion(conclusion)
+        .output(CheckRunOutput {
+            title: "Benchmark".to_owned(),
+            summary: reason.to_string(),
+            text: None,
+            annotations: vec![],
+            images: vec![],
+        })
+        .send()
+        .await?;
+    check.set_actions(&[RERUN]).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_section() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let log = JobLog::create(tmp_dir.as_path_untracked().join("0.log")).unwrap();
+        std::fs::write(log.path(), "· discovering\n```\n").unwrap();
+        let section = log_section(&log, Some("https://example.com/logs/0.log"));
+        assert_eq!(
+            section,
+            "## Log ([full log](https://example.com/logs/0.log))\n\n```text\n· discovering\n` ` `\n```"
+        );
+        assert!(log_section(&log, None).starts_with("## Log\n"));
+    }
+
+    #[test]
+    fn test_log_section_long_lines() {
+        let tmp_dir = test_temp_dir::test_temp_dir!();
+        let log = JobLog::create(tmp_dir.as_path_untracked().join("0.log")).unwrap();
+        let lines: Vec<_> = (0..LOG_TAIL_LINES)
+            .map(|i| format!("line {i} {}", "x".repeat(10_000)))
+            .collect();
+        std::fs::write(log.path(), lines.join("\n")).unwrap();
+        let section = log_section(&log, None);
+        assert!(section.len() <= LOG_TAIL_BYTES + "## Log\n\n```text\n\n```".len());
+        // the end of the log is kept
+        assert!(section.contains("line 99 "));
+        assert!(!section.contains("line 97 "));
+        assert!(section.ends_with("x\n```"));
+    }
+
+    #[test]
+    fn test_env_conclusion() {
+        let mut comparison = crate::fixtures::fixture_comparison();
+        let (conclusion, summary) = env_conclusion(&comparison);
+        assert!(matches!(conclusion, CheckRunConclusion::Failure));
+        assert_eq!(summary, "1 newly failing, 1 slower, 1 faster");
+
+        comparison.rows.retain(|r| r.change != Change::Failed);
+        let (conclusion, _) = env_conclusion(&comparison);
+        assert!(matches!(conclusion, CheckRunConclusion::Neutral));
+
+        comparison.rows.retain(|r| r.change != Change::Worse);
+        let (conclusion, _) = env_conclusion(&comparison);
+        assert!(matches!(conclusion, CheckRunConclusion::Success));
+
+        let (conclusion, summary) = env_conclusion(&comparison.for_env("conda-py3.11"));
+        assert!(matches!(conclusion, CheckRunConclusion::Neutral));
+        assert_eq!(summary, "No results in this environment");
+    }
+
+    #[test]
+    fn test_env_label() {
+        assert_eq!(env_label("virtualenv-py3.12-numpy2"), "py3.12-numpy2");
+        assert_eq!(env_label("existing"), "existing");
+    }
+
+    #[test]
+    fn test_progress_summary() {
+        let mut progress = Progress {
+            percent: 0.,
+            benchmark: None,
+            started: std::time::Instant::now(),
+        };
+        assert_eq!(progress_summary(&progress), "0% done");
+        progress.started = progress
+            .started
+            .checked_sub(Duration::from_secs(90))
+            .unwrap();
+        progress.percent = 50.;
+        assert_eq!(progress_summary(&progress), "50% done, about 1m 30s left");
+    }
+}
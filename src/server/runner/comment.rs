@@ -1,86 +1,546 @@
-use anyhow::Result;
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
 use askama::Template;
 use chrono::{DateTime, Utc};
+use octocrab::Octocrab;
+use serde::{Deserialize, Serialize};
 
-use crate::constants::{is_pr_comparison, ORG, PR_COMPARISON_MARKER};
+use crate::benchmark::comparison::{cell, code_cell, short, Change, Comparison, Row};
+use crate::constants::{is_pr_comparison, PR_COMPARISON_MARKER};
 use crate::event::Compare;
 use crate::octocrab_utils::PageExt;
+use crate::repo_config::Comments;
+use crate::server::octocrab_utils::clamp_lines;
 
-#[tracing::instrument(skip_all)]
-pub(super) async fn update(cmp: &Compare, markdown: &str, success: bool) -> Result<()> {
-    let markdown = make(cmp, markdown, success)?;
+use super::checks::env_label;
+
+/// GitHub rejects comments longer than this many characters
+const MAX_COMMENT_LEN: usize = 65_536;
 
-    tracing::info!("Updating comment for {ORG}/{}’s PR {}", cmp.repo, cmp.pr);
-    let github_api = octocrab::instance();
-    let issue_api = github_api.issues(ORG, &cmp.repo);
-    if let Some(comment) = issue_api
+/// Precedes the runs listed in a comment, stored as JSON in an HTML comment
+const HISTORY_MARKER: &str = "<!-- benchmark history: ";
+
+/// How many runs a comment lists
+const MAX_HISTORY: usize = 20;
+
+/// Comment on the PR with the comparison, keeping earlier results as configured.
+#[tracing::instrument(skip_all)]
+pub(super) async fn update(
+    github_api: &Octocrab,
+    cmp: &Compare,
+    comparison: &Comparison,
+    success: bool,
+    site_url: Option<&str>,
+    chart_url: Option<&str>,
+    mode: Comments,
+) -> Result<()> {
+    tracing::info!(
+        "Updating comment for {}/{}’s PR {}",
+        cmp.owner,
+        cmp.repo,
+        cmp.pr
+    );
+    let issue_api = github_api.issues(&cmp.owner, &cmp.repo);
+    let previous = issue_api
         .list_comments(cmp.pr)
         .send()
         .await?
-        .find(&github_api, is_pr_comparison)
-        .await?
-    {
+        .filter(github_api, is_pr_comparison)
+        .await?;
+    let current = PastRun {
+        commit: cmp.commits[1].clone(),
+        date: Utc::now(),
+        counts: counts(comparison),
+    };
+    let latest = previous.last().filter(|_| mode == Comments::History);
+    if let Some(comment) = latest {
+        let history = parse_history(comment.body.as_deref().unwrap_or_default());
+        let history = add_run(history, current);
+        let markdown = make(cmp, comparison, success, site_url, chart_url, &history)?;
         issue_api.update_comment(comment.id, markdown).await?;
         tracing::info!("Updated comment at {}", comment.html_url);
     } else {
+        let markdown = make(cmp, comparison, success, site_url, chart_url, &[current])?;
         let comment = issue_api.create_comment(cmp.pr, markdown).await?;
         tracing::info!("Created comment at {}", comment.html_url);
+        // earlier comments were hidden when their successors were posted
+        if let Some(outdated) = previous.last() {
+            if let Err(e) = minimize(github_api, &outdated.node_id).await {
+                tracing::warn!("Failed to hide comment {}: {e:?}", outdated.html_url);
+            }
+        }
     }
     Ok(())
 }
 
+/// Hide a comment as outdated, which is only possible via GraphQL.
+async fn minimize(github_api: &Octocrab, node_id: &str) -> Result<()> {
+    let response: serde_json::Value = github_api
+        .graphql(&serde_json::json!({
+            "query": "mutation($id: ID!) { minimizeComment(input: {subjectId: $id, classifier: OUTDATED}) { clientMutationId } }",
+            "variables": { "id": node_id },
+        }))
+        .await?;
+    if let Some(errors) = response.get("errors") {
+        bail!("GraphQL errors: {errors}");
+    }
+    Ok(())
+}
+
+/// A benchmark run listed in a comment
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct PastRun {
+    /// The PR’s head commit that was benchmarked
+    commit: String,
+    date: DateTime<Utc>,
+    counts: Counts,
+}
+
+impl PastRun {
+    fn short_commit(&self) -> &str {
+        short(&self.commit)
+    }
+}
+
+/// Read the runs listed in a comment, most recent first.
+///
+/// Comments without a (valid) list, e.g. from before lists were added, have none.
+fn parse_history(body: &str) -> Vec<PastRun> {
+    body.split_once(HISTORY_MARKER)
+        .and_then(|(_, rest)| rest.split_once(" -->"))
+        .and_then(|(json, _)| serde_json::from_str(json).ok())
+        .unwrap_or_default()
+}
+
+/// Add the `current` run to the front, replacing a run of the same commit.
+fn add_run(mut history: Vec<PastRun>, current: PastRun) -> Vec<PastRun> {
+    history.retain(|run| run.commit != current.commit);
+    history.insert(0, current);
+    history.truncate(MAX_HISTORY);
+    history
+}
+
 #[derive(Template)]
 #[template(path = "comment.md.j2", escape = "none")]
 struct Comment<'a> {
     pr_comparison_marker: &'a str,
-    content: &'a str,
+    history_marker: &'a str,
     now: DateTime<Utc>,
     cmp: &'a Compare,
     success: bool,
+    /// Where the results are published as HTML site
+    site_url: Option<&'a str>,
+    /// Where the chart of the changes is served
+    chart_url: Option<&'a str>,
+    /// This run followed by earlier ones
+    history: &'a [PastRun],
+    /// `history` as JSON, to be read back by the next run
+    history_json: String,
+    /// Abbreviated commits compared
+    before: &'a str,
+    after: &'a str,
+    counts: Counts,
+    /// Significant changes, sorted by ratio
+    summary: Vec<Line>,
+    /// How many significant changes were left out of `summary` to keep the comment short enough
+    omitted: usize,
+    /// All results, empty if listing them would make the comment too long
+    envs: Vec<EnvSection<'a>>,
 }
 
-fn make(cmp: &Compare, content: &str, success: bool) -> Result<String> {
-    Ok(Comment {
+/// How many benchmarks changed significantly
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Counts {
+    improved: usize,
+    regressed: usize,
+    failed: usize,
+    fixed: usize,
+}
+
+/// Results in one environment, grouped by benchmark suite
+struct EnvSection<'a> {
+    label: &'a str,
+    changed: usize,
+    suites: Vec<SuiteSection<'a>>,
+}
+
+struct SuiteSection<'a> {
+    name: &'a str,
+    changed: usize,
+    lines: Vec<Line>,
+}
+
+/// A row of a comparison, formatted for a table
+struct Line {
+    /// Marks significant changes, empty otherwise
+    marker: &'static str,
+    /// Benchmark name and parameters as inline code
+    name: String,
+    /// The benchmark’s graph on the asv site
+    graph: Option<String>,
+    env: String,
+    before: String,
+    after: String,
+    ratio: String,
+}
+
+impl Line {
+    fn new(row: &Row) -> Self {
+        let marker = match row.change {
+            Change::Failed => "**failing**",
+            Change::Worse => "**slower**",
+            Change::Better => "**faster**",
+            Change::Fixed => "**fixed**",
+            _ => "",
+        };
+        let [before, after] = row.measurements().map(|m| cell(&m));
+        let ratio = row
+            .ratio()
+            .map_or_else(|| "n/a".to_owned(), |r| format!("{r:.2}"));
+        Self {
+            marker,
+            name: code_cell(&row.name()),
+            graph: None,
+            env: cell(env_label(&row.env)),
+            before,
+            after,
+            ratio: if marker.is_empty() {
+                ratio
+            } else {
+                format!("**{ratio}**")
+            },
+        }
+    }
+}
+
+/// Whether a row is counted and summarized
+fn is_significant(row: &Row) -> bool {
+    matches!(
+        row.change,
+        Change::Failed | Change::Worse | Change::Better | Change::Fixed
+    )
+}
+
+fn counts(comparison: &Comparison) -> Counts {
+    let mut counts = Counts::default();
+    for row in &comparison.rows {
+        match row.change {
+            Change::Failed => counts.failed += 1,
+            Change::Worse => counts.regressed += 1,
+            Change::Better => counts.improved += 1,
+            Change::Fixed => counts.fixed += 1,
+            _ => {}
+        }
+    }
+    counts
+}
+
+/// Significant changes, failures and fixes first, then from the biggest regression to the biggest improvement.
+///
+/// Lines link to their benchmark’s graph if the asv site is published at `site_url`.
+fn summary(comparison: &Comparison, site_url: Option<&str>) -> Vec<Line> {
+    let mut rows: Vec<&Row> = comparison
+        .rows
+        .iter()
+        .filter(|r| is_significant(r))
+        .collect();
+    rows.sort_by(|a, b| match (a.ratio(), b.ratio()) {
+        (Some(a), Some(b)) => b.total_cmp(&a),
+        (a, b) => a.is_some().cmp(&b.is_some()),
+    });
+    rows.into_iter()
+        .map(|row| Line {
+            // the asv site selects a benchmark’s graph by its full name
+            graph: site_url.map(|url| cell(&format!("{url}#{}", row.benchmark))),
+            ..Line::new(row)
+        })
+        .collect()
+}
+
+fn env_sections(comparison: &Comparison) -> Vec<EnvSection<'_>> {
+    let mut by_env: BTreeMap<&str, BTreeMap<&str, Vec<&Row>>> = BTreeMap::new();
+    for row in &comparison.rows {
+        by_env
+            .entry(&row.env)
+            .or_default()
+            .entry(row.suite())
+            .or_default()
+            .push(row);
+    }
+    by_env
+        .into_iter()
+        .map(|(env, suites)| {
+            let suites: Vec<SuiteSection> = suites
+                .into_iter()
+                .map(|(name, rows)| SuiteSection {
+                    name,
+                    changed: rows.iter().filter(|r| is_significant(r)).count(),
+                    lines: rows.into_iter().map(Line::new).collect(),
+                })
+                .collect();
+            EnvSection {
+                label: env_label(env),
+                changed: suites.iter().map(|s| s.changed).sum(),
+                suites,
+            }
+        })
+        .collect()
+}
+
+/// Render the comment, linking to the site and chart. `history` starts with the current run.
+fn make(
+    cmp: &Compare,
+    comparison: &Comparison,
+    success: bool,
+    site_url: Option<&str>,
+    chart_url: Option<&str>,
+    history: &[PastRun],
+) -> Result<String> {
+    let mut comment = Comment {
         pr_comparison_marker: PR_COMPARISON_MARKER,
-        content,
+        history_marker: HISTORY_MARKER,
+        now: history.first().map_or_else(Utc::now, |run| run.date),
         cmp,
-        now: Utc::now(),
+        history,
+        history_json: serde_json::to_string(history)?,
         success,
+        site_url,
+        chart_url,
+        before: short(&comparison.before),
+        after: short(&comparison.after),
+        counts: counts(comparison),
+        summary: summary(comparison, site_url),
+        omitted: 0,
+        envs: env_sections(comparison),
+    };
+    let markdown = comment.render()?;
+    if markdown.len() <= MAX_COMMENT_LEN {
+        return Ok(markdown);
+    }
+    // the check runs still list everything
+    comment.envs.clear();
+    let mut markdown = comment.render()?;
+    while markdown.len() > MAX_COMMENT_LEN && !comment.summary.is_empty() {
+        // drop the smallest changes, proportionally to how much too long the comment is
+        let keep = (comment.summary.len() * MAX_COMMENT_LEN / markdown.len())
+            .min(comment.summary.len() - 1);
+        comment.omitted += comment.summary.len() - keep;
+        comment.summary.truncate(keep);
+        markdown = comment.render()?;
     }
-    .render()?)
+    Ok(clamp_lines(&markdown, MAX_COMMENT_LEN).to_owned())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    use crate::benchmark::comparison::Thresholds;
+    use crate::constants::ORG;
+    use crate::event::Filter;
+    use crate::fixtures::fixture_comparison;
     use octocrab::models::CheckRunId;
     use rstest::rstest;
 
+    fn compare(check_id: Option<CheckRunId>) -> Compare {
+        Compare {
+            owner: ORG.to_owned(),
+            repo: "repo2".to_owned(),
+            installation: None,
+            pr: 2,
+            fork: None,
+            commits: ["c".to_owned(), "d".to_owned()],
+            check_id,
+            filter: Filter::default(),
+        }
+    }
+
+    fn past_run(commit: &str, comparison: &Comparison) -> PastRun {
+        PastRun {
+            commit: commit.to_owned(),
+            date: "2025-03-01T12:00:00Z".parse().unwrap(),
+            counts: counts(comparison),
+        }
+    }
+
     #[rstest]
     fn test_make(
         #[values(true, false)] success: bool,
-        #[values("", "Some | Table")] content: &str,
+        #[values(true, false)] changed: bool,
         #[values(None, Some(3u64.into()))] check_id: Option<CheckRunId>,
+        #[values(None, Some("https://bench.example.com/sites/scverse/repo2/"))] site_url: Option<
+            &str,
+        >,
+        #[values(None, Some("https://bench.example.com/charts/scverse/repo2/c-d.svg"))]
+        chart_url: Option<&str>,
     ) {
-        let cmp = Compare {
-            repo: "repo2".to_owned(),
-            pr: 2,
-            commits: ["c".to_owned(), "d".to_owned()],
-            check_id,
-        };
-        let markdown = make(&cmp, content, success).unwrap();
+        let mut comparison = fixture_comparison();
+        if !changed {
+            comparison.rows.retain(|r| !is_significant(r));
+        }
+        let markdown = make(
+            &compare(check_id),
+            &comparison,
+            success,
+            site_url,
+            chart_url,
+            &[past_run("d", &comparison)],
+        )
+        .unwrap();
         assert!(markdown.contains(PR_COMPARISON_MARKER));
+        assert_eq!(changed, markdown.contains("## Benchmark changes"));
         assert_eq!(
-            !content.is_empty(),
-            markdown.contains("## Benchmark changes")
+            !changed,
+            markdown.contains("No significant changes in benchmarks.")
         );
-        assert!(markdown.contains(content));
+        assert!(markdown.contains("<details><summary>All results in py3.12"));
+        assert!(!markdown.contains("Earlier runs"));
         assert_eq!(!success, markdown.contains("> [!WARNING]"));
         assert_eq!(check_id.is_some(), markdown.contains("More details:"));
+        assert_eq!(
+            site_url.is_some(),
+            markdown.contains("Graphs: <https://bench.example.com/sites/scverse/repo2/>")
+        );
+        assert_eq!(
+            changed && site_url.is_some(),
+            markdown.contains(
+                "| **slower** | `TimeSuite.time_iterkeys` [(graph)](https://bench.example.com/sites/scverse/repo2/#benchmarks.TimeSuite.time_iterkeys) |"
+            )
+        );
+        // only shown along with the changes
+        assert_eq!(
+            changed && chart_url.is_some(),
+            markdown.contains(
+                "![Benchmark changes, relative to before](https://bench.example.com/charts/scverse/repo2/c-d.svg)"
+            )
+        );
         if check_id.is_some() {
             assert!(markdown.contains(
                 "More details: <https://github.com/scverse/repo2/pull/2/checks?check_run_id=3>"
             ));
         }
     }
+
+    #[test]
+    fn test_summary() {
+        let comparison = fixture_comparison();
+        assert_eq!(
+            counts(&comparison),
+            Counts {
+                improved: 1,
+                regressed: 1,
+                failed: 1,
+                fixed: 0
+            }
+        );
+        let summary: Vec<_> = summary(&comparison, None)
+            .into_iter()
+            .map(|line| (line.marker, line.name, line.ratio))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "**failing**",
+                    "`ParamSuite.time_sum(10, 'float')`".to_owned(),
+                    "**n/a**".to_owned()
+                ),
+                (
+                    "**slower**",
+                    "`TimeSuite.time_iterkeys`".to_owned(),
+                    "**2.50**".to_owned()
+                ),
+                (
+                    "**faster**",
+                    "`ParamSuite.time_sum(100, 'int')`".to_owned(),
+                    "**0.50**".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_make_long() {
+        let mut comparison = fixture_comparison();
+        let rows = std::mem::take(&mut comparison.rows);
+        for i in 0..3000 {
+            comparison.rows.extend(rows.iter().cloned().map(|mut row| {
+                row.params = vec![format!("'{i}|`{}`'", "x".repeat(20))];
+                row
+            }));
+        }
+        let markdown = make(
+            &compare(None),
+            &comparison,
+            true,
+            None,
+            None,
+            &[past_run("d", &comparison)],
+        )
+        .unwrap();
+        assert!(markdown.len() <= MAX_COMMENT_LEN);
+        assert!(!markdown.contains("<details><summary>All results in"));
+        assert!(markdown.contains("more, see the check runs for all results."));
+        // the end of the comment is intact
+        assert_eq!(parse_history(&markdown), [past_run("d", &comparison)]);
+        // table rows aren’t split by the pipes in parameters
+        let row = markdown
+            .lines()
+            .find(|line| line.starts_with("| **") && line.contains("|`"))
+            .unwrap();
+        assert_eq!(row.matches(" | ").count(), 5, "{row}");
+    }
+
+    #[test]
+    fn test_env_sections() {
+        let comparison = fixture_comparison();
+        let [env] = env_sections(&comparison).try_into().ok().unwrap();
+        assert_eq!((env.label, env.changed), ("py3.12", 3));
+        let suites: Vec<_> = env
+            .suites
+            .iter()
+            .map(|s| (s.name, s.changed, s.lines.len()))
+            .collect();
+        assert_eq!(
+            suites,
+            [
+                ("MemSuite", 0, 1),
+                ("ParamSuite", 2, 3),
+                ("TimeSuite", 1, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_history() {
+        let comparison = fixture_comparison();
+        let older = past_run(
+            "0123456789abcdef",
+            &Comparison::new(
+                ["a", "b"],
+                [&[], &[]],
+                &BTreeMap::new(),
+                &Thresholds::default(),
+            ),
+        );
+        let history = add_run(vec![older.clone()], past_run("d", &comparison));
+        let markdown = make(&compare(None), &comparison, true, None, None, &history).unwrap();
+        assert!(markdown.contains("<details><summary>Earlier runs</summary>"));
+        assert!(markdown.contains(
+            "| [01234567](https://github.com/scverse/repo2/pull/2/commits/0123456789abcdef) | 2025-03-01 12:00 UTC | 0 | 0 | 0 |"
+        ));
+        assert_eq!(parse_history(&markdown), history);
+
+        // re-running a commit replaces its entry
+        let rerun = add_run(parse_history(&markdown), past_run("d", &comparison));
+        assert_eq!(rerun, history);
+        assert_eq!(parse_history("no history here"), []);
+
+        let long = (0..MAX_HISTORY + 5).fold(vec![], |history, i| {
+            add_run(history, past_run(&i.to_string(), &comparison))
+        });
+        assert_eq!(long.len(), MAX_HISTORY);
+        assert_eq!(long[0].commit, (MAX_HISTORY + 4).to_string());
+    }
 }
@@ -0,0 +1,154 @@
+use anyhow::{Context, Result};
+use clap::{Args, ValueEnum};
+use serde::Deserialize;
+
+/// A user’s permission level on a repository, see
+/// <https://docs.github.com/en/rest/collaborators/collaborators#get-repository-permissions-for-a-user>
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Permission {
+    None,
+    Read,
+    Write,
+    Admin,
+}
+
+#[derive(Deserialize)]
+struct PermissionResponse {
+    permission: Permission,
+}
+
+/// Who may run code on the benchmark machine.
+///
+/// A user is trusted if any of the configured checks passes.
+#[derive(Args, Debug, Clone, Default)]
+pub(crate) struct TrustPolicy {
+    /// User who may always trigger benchmark runs (can be repeated)
+    #[arg(long = "trusted-user", value_name = "LOGIN")]
+    pub(crate) users: Vec<String>,
+    /// Trust members of the organization owning the repository
+    #[arg(long = "trust-org-members", default_value_t = true, action = clap::ArgAction::Set)]
+    pub(crate) org_members: bool,
+    /// Trust users with at least this permission on the repository (default: don’t check permissions)
+    #[arg(long)]
+    pub(crate) min_permission: Option<Permission>,
+}
+
+impl TrustPolicy {
+    /// Check via the GitHub API if `user` may run benchmarks in `owner/repo`.
+    #[tracing::instrument(skip(self, github_client))]
+    pub(crate) async fn is_trusted(
+        &self,
+        github_client: &octocrab::Octocrab,
+        owner: &str,
+        repo: &str,
+        user: &str,
+    ) -> Result<bool> {
+        if self.users.iter().any(|u| u.eq_ignore_ascii_case(user)) {
+            return Ok(true);
+        }
+        if self.org_members
+            && github_client
+                .orgs(owner)
+                .check_membership(user)
+                .await
+                .context("failed to check org membership")?
+        {
+            return Ok(true);
+        }
+        if let Some(min_permission) = self.min_permission {
+            let PermissionResponse { permission } = github_client
+                .get(
+                    format!("/repos/{owner}/{repo}/collaborators/{user}/permission"),
+                    None::<&()>,
+                )
+                .await
+                .context("failed to get repository permission")?;
+            return Ok(permission >= min_permission);
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::constants::ORG;
+    use rstest::rstest;
+    use serde_json::json;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    async fn github_client(member: bool, permission: &str) -> (octocrab::Octocrab, MockServer) {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(format!("/orgs/{ORG}/members/someone")))
+            .respond_with(ResponseTemplate::new(if member { 204 } else { 404 }))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!(
+                "/repos/{ORG}/repo/collaborators/someone/permission"
+            )))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({ "permission": permission })),
+            )
+            .mount(&mock_server)
+            .await;
+        let client = octocrab::Octocrab::builder()
+            .base_uri(mock_server.uri())
+            .unwrap()
+            .build()
+            .unwrap();
+        (client, mock_server)
+    }
+
+    #[rstest]
+    #[case::allowlisted(&["SomeOne"], false, None, false, "none", true)]
+    #[case::member(&[], true, None, true, "none", true)]
+    #[case::member_unchecked(&[], false, None, true, "none", false)]
+    #[case::writer(&[], false, Some(Permission::Write), false, "write", true)]
+    #[case::admin(&[], false, Some(Permission::Write), false, "admin", true)]
+    #[case::reader(&[], false, Some(Permission::Write), false, "read", false)]
+    #[tokio::test]
+    async fn test_is_trusted(
+        #[case] users: &[&str],
+        #[case] org_members: bool,
+        #[case] min_permission: Option<Permission>,
+        #[case] member: bool,
+        #[case] permission: &str,
+        #[case] expected: bool,
+    ) {
+        let (client, _server) = github_client(member, permission).await;
+        let policy = TrustPolicy {
+            users: users.iter().copied().map(str::to_owned).collect(),
+            org_members,
+            min_permission,
+        };
+        let trusted = policy
+            .is_trusted(&client, ORG, "repo", "someone")
+            .await
+            .unwrap();
+        assert_eq!(trusted, expected);
+    }
+
+    #[derive(clap::Parser)]
+    struct Cli {
+        #[command(flatten)]
+        trust: TrustPolicy,
+    }
+
+    #[rstest]
+    #[case::unchecked(&[], None)]
+    #[case::writer(&["--min-permission", "write"], Some(Permission::Write))]
+    fn test_parse_min_permission(#[case] args: &[&str], #[case] expected: Option<Permission>) {
+        use clap::Parser;
+
+        let cli =
+            Cli::try_parse_from(std::iter::once("serve").chain(args.iter().copied())).unwrap();
+        assert_eq!(cli.trust.min_permission, expected);
+    }
+}
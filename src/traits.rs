@@ -1,5 +1,14 @@
+use crate::event::Filter;
+use crate::repo_cache::PrHead;
+
 pub(crate) trait RunConfig {
+    fn owner(&self) -> &str;
     fn repo(&self) -> &str;
     fn config_ref(&self) -> Option<&str>;
     fn run_on(&self) -> &[String];
+    /// Commit to read the repository configuration from (default: the checked out `config_ref`)
+    fn base_ref(&self) -> Option<&str>;
+    /// The PR head to fetch before syncing, which might not be in the repository’s branches
+    fn pr_head(&self) -> Option<PrHead>;
+    fn filter(&self) -> &Filter;
 }
@@ -19,16 +19,29 @@ pub(crate) fn get_credential(_name: &str) -> anyhow::Result<secrecy::SecretStrin
     Err(anyhow::anyhow!("No way to get credential on this OS"))
 }
 
-#[cfg(target_os = "linux")]
+/// Format seconds as e.g. `1h 2m 3s`, leaving out leading zero units.
+pub(crate) fn format_duration(seconds: i64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{hours}h {minutes}m {seconds}s")
+    } else if minutes > 0 {
+        format!("{minutes}m {seconds}s")
+    } else {
+        format!("{seconds}s")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use rstest::rstest;
     use secrecy::ExposeSecret;
     use std::{fs::File, io::Write};
 
     const TEST_SECRET: &str = "It's a Secret to Everybody";
 
+    #[cfg(target_os = "linux")]
     #[test]
     fn test_load_credential() {
         let tmp_dir = test_temp_dir::test_temp_dir!();
@@ -45,4 +58,14 @@ mod tests {
 
         assert_eq!(cred.expose_secret(), TEST_SECRET);
     }
+
+    #[rstest]
+    #[case(0, "0s")]
+    #[case(59, "59s")]
+    #[case(61, "1m 1s")]
+    #[case(3600, "1h 0m 0s")]
+    #[case(3725, "1h 2m 5s")]
+    fn test_format_duration(#[case] seconds: i64, #[case] expected: &str) {
+        assert_eq!(format_duration(seconds), expected);
+    }
 }